@@ -0,0 +1,12 @@
+-- Migration: Per-key todo ownership
+-- Created: 2025-09-02
+
+-- Each todo belongs to the API key that created it
+ALTER TABLE todos ADD COLUMN owner_key_id TEXT REFERENCES api_keys(id);
+
+-- Existing todos were shared by every key; hand them to the oldest admin key
+UPDATE todos SET owner_key_id = (
+    SELECT id FROM api_keys WHERE key_type = 'admin' ORDER BY created_at ASC LIMIT 1
+) WHERE owner_key_id IS NULL;
+
+CREATE INDEX idx_todos_owner_key_id ON todos(owner_key_id);
@@ -0,0 +1,12 @@
+-- Migration: Track emergency reinitialization attempts
+-- Created: 2025-09-04
+
+-- Every POST /reinitialize attempt is recorded so failed guesses can be throttled
+CREATE TABLE recovery_attempts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    client_ip TEXT NOT NULL,
+    success INTEGER NOT NULL DEFAULT 0,
+    attempted_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_recovery_attempts_ip_time ON recovery_attempts(client_ip, attempted_at);
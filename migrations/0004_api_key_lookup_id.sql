@@ -0,0 +1,8 @@
+-- Migration: Public lookup identifier for API keys
+-- Created: 2025-09-06
+
+-- New keys embed a short, non-secret id (pali_<lookup_id>_<secret>) so validation can
+-- fetch the row directly instead of hashing first. Legacy keys keep a NULL lookup_id.
+ALTER TABLE api_keys ADD COLUMN lookup_id TEXT;
+
+CREATE UNIQUE INDEX idx_api_keys_lookup_id ON api_keys(lookup_id);
@@ -0,0 +1,20 @@
+-- Migration: Tags for todos
+-- Created: 2025-09-09
+
+-- Tags belong to the same API key as the todos they label
+CREATE TABLE tags (
+    id TEXT PRIMARY KEY,
+    owner_key_id TEXT NOT NULL REFERENCES api_keys(id),
+    name TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    UNIQUE(owner_key_id, name)
+);
+
+-- Many-to-many link between todos and tags
+CREATE TABLE todo_tags (
+    todo_id TEXT NOT NULL REFERENCES todos(id) ON DELETE CASCADE,
+    tag_id TEXT NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (todo_id, tag_id)
+);
+
+CREATE INDEX idx_todo_tags_tag_id ON todo_tags(tag_id);
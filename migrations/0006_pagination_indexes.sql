@@ -0,0 +1,6 @@
+-- Migration: Indexes backing cursor pagination
+-- Created: 2025-09-12
+
+-- Match the keyset ORDER BY used by GET /todos and GET /admin/keys
+CREATE INDEX idx_todos_owner_list_order ON todos(owner_key_id, priority DESC, created_at DESC, id DESC);
+CREATE INDEX idx_api_keys_list_order ON api_keys(created_at DESC, id DESC);
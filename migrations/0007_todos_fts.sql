@@ -0,0 +1,30 @@
+-- Migration: Full-text search index for todos
+-- Created: 2025-09-14
+
+-- Standalone FTS5 table keyed by todo id; todos has no INTEGER PRIMARY KEY,
+-- so its implicit rowid is not stable enough for an external-content table
+CREATE VIRTUAL TABLE todos_fts USING fts5(
+    todo_id UNINDEXED,
+    title,
+    description,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+-- Keep the index in sync with every write to todos
+CREATE TRIGGER todos_fts_after_insert AFTER INSERT ON todos BEGIN
+    INSERT INTO todos_fts (todo_id, title, description)
+    VALUES (new.id, new.title, COALESCE(new.description, ''));
+END;
+
+CREATE TRIGGER todos_fts_after_update AFTER UPDATE OF title, description ON todos BEGIN
+    UPDATE todos_fts SET title = new.title, description = COALESCE(new.description, '')
+    WHERE todo_id = old.id;
+END;
+
+CREATE TRIGGER todos_fts_after_delete AFTER DELETE ON todos BEGIN
+    DELETE FROM todos_fts WHERE todo_id = old.id;
+END;
+
+-- Index existing todos
+INSERT INTO todos_fts (todo_id, title, description)
+SELECT id, title, COALESCE(description, '') FROM todos;
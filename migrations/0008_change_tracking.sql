@@ -0,0 +1,55 @@
+-- Migration: Change sequence and deletion tombstones for delta sync
+-- Created: 2025-09-16
+
+-- Single-row counter; every change to a todo takes the next value
+CREATE TABLE change_counter (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    seq INTEGER NOT NULL
+);
+INSERT INTO change_counter (id, seq) VALUES (1, 1);
+
+-- Sequence of the last change to each todo (including its tags)
+ALTER TABLE todos ADD COLUMN change_seq INTEGER NOT NULL DEFAULT 0;
+
+-- Existing todos count as one initial change so a full sync (since=0) returns them
+UPDATE todos SET change_seq = 1;
+
+-- Deleted todos, kept so clients can drop them from their local copy
+CREATE TABLE todo_tombstones (
+    todo_id TEXT PRIMARY KEY,
+    owner_key_id TEXT,
+    change_seq INTEGER NOT NULL,
+    deleted_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_todos_owner_change_seq ON todos(owner_key_id, change_seq);
+CREATE INDEX idx_todo_tombstones_owner_change_seq ON todo_tombstones(owner_key_id, change_seq);
+
+-- Stamp inserted and updated todos. The WHEN guard stops the stamping UPDATE from re-firing.
+CREATE TRIGGER todos_change_after_insert AFTER INSERT ON todos BEGIN
+    UPDATE change_counter SET seq = seq + 1 WHERE id = 1;
+    UPDATE todos SET change_seq = (SELECT seq FROM change_counter WHERE id = 1) WHERE id = new.id;
+END;
+
+CREATE TRIGGER todos_change_after_update AFTER UPDATE ON todos
+WHEN new.change_seq = old.change_seq BEGIN
+    UPDATE change_counter SET seq = seq + 1 WHERE id = 1;
+    UPDATE todos SET change_seq = (SELECT seq FROM change_counter WHERE id = 1) WHERE id = new.id;
+END;
+
+CREATE TRIGGER todos_change_after_delete AFTER DELETE ON todos BEGIN
+    UPDATE change_counter SET seq = seq + 1 WHERE id = 1;
+    INSERT OR REPLACE INTO todo_tombstones (todo_id, owner_key_id, change_seq, deleted_at)
+    VALUES (old.id, old.owner_key_id, (SELECT seq FROM change_counter WHERE id = 1), CAST(strftime('%s', 'now') AS INTEGER));
+END;
+
+-- Tags are part of a todo's synced state, so relinking them counts as a change to the todo
+CREATE TRIGGER todo_tags_change_after_insert AFTER INSERT ON todo_tags BEGIN
+    UPDATE change_counter SET seq = seq + 1 WHERE id = 1;
+    UPDATE todos SET change_seq = (SELECT seq FROM change_counter WHERE id = 1) WHERE id = new.todo_id;
+END;
+
+CREATE TRIGGER todo_tags_change_after_delete AFTER DELETE ON todo_tags BEGIN
+    UPDATE change_counter SET seq = seq + 1 WHERE id = 1;
+    UPDATE todos SET change_seq = (SELECT seq FROM change_counter WHERE id = 1) WHERE id = old.todo_id;
+END;
@@ -0,0 +1,5 @@
+-- Migration: Per-todo version for optimistic concurrency
+-- Created: 2025-09-18
+
+-- Incremented by every write; exposed as the todo's ETag and checked against If-Match
+ALTER TABLE todos ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
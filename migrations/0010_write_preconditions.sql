@@ -0,0 +1,16 @@
+-- Migration: Precondition guard for multi-statement writes
+-- Created: 2025-09-20
+
+-- D1 batches only roll back when a statement fails, and a conditional UPDATE that matches
+-- nothing does not fail. Inserting into this table evaluates a precondition instead:
+-- a true value is silently dropped, a false one aborts the whole batch.
+CREATE TABLE write_preconditions (
+    ok INTEGER NOT NULL
+);
+
+CREATE TRIGGER write_preconditions_check BEFORE INSERT ON write_preconditions BEGIN
+    SELECT CASE
+        WHEN new.ok THEN RAISE(IGNORE)
+        ELSE RAISE(ABORT, 'write precondition failed')
+    END;
+END;
@@ -0,0 +1,17 @@
+-- Migration: Idempotency keys for mutating requests
+-- Created: 2025-09-22
+
+-- One row per (API key, Idempotency-Key). status_code stays NULL while the original
+-- request is still running; afterwards the row holds the response to replay.
+CREATE TABLE idempotency_keys (
+    api_key_id TEXT NOT NULL REFERENCES api_keys(id) ON DELETE CASCADE,
+    idempotency_key TEXT NOT NULL,
+    request_hash TEXT NOT NULL,
+    status_code INTEGER,
+    response_headers TEXT,
+    response_body TEXT,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (api_key_id, idempotency_key)
+);
+
+CREATE INDEX idx_idempotency_keys_created_at ON idempotency_keys(created_at);
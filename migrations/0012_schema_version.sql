@@ -0,0 +1,25 @@
+-- Migration: Schema version tracking for the built-in migration runner
+-- Created: 2025-09-24
+
+-- One row per applied migration; the schema version is the highest row.
+-- Every migration from here on must insert its own row as its last statement,
+-- so applying it with wrangler or with POST /admin/schema/migrate records it the same way.
+CREATE TABLE schema_version (
+    version INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    applied_at INTEGER NOT NULL
+);
+
+INSERT INTO schema_version (version, name, applied_at) VALUES
+    (1, '0001_initial_schema', CAST(strftime('%s', 'now') AS INTEGER)),
+    (2, '0002_todo_ownership', CAST(strftime('%s', 'now') AS INTEGER)),
+    (3, '0003_recovery_attempts', CAST(strftime('%s', 'now') AS INTEGER)),
+    (4, '0004_api_key_lookup_id', CAST(strftime('%s', 'now') AS INTEGER)),
+    (5, '0005_tags', CAST(strftime('%s', 'now') AS INTEGER)),
+    (6, '0006_pagination_indexes', CAST(strftime('%s', 'now') AS INTEGER)),
+    (7, '0007_todos_fts', CAST(strftime('%s', 'now') AS INTEGER)),
+    (8, '0008_change_tracking', CAST(strftime('%s', 'now') AS INTEGER)),
+    (9, '0009_todo_versions', CAST(strftime('%s', 'now') AS INTEGER)),
+    (10, '0010_write_preconditions', CAST(strftime('%s', 'now') AS INTEGER)),
+    (11, '0011_idempotency_keys', CAST(strftime('%s', 'now') AS INTEGER)),
+    (12, '0012_schema_version', CAST(strftime('%s', 'now') AS INTEGER));
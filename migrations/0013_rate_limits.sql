@@ -0,0 +1,21 @@
+-- Migration: Token buckets for per-key and per-IP rate limiting
+-- Created: 2025-09-26
+
+-- Optional per-key budget in requests per minute; NULL uses the default for the key's type
+ALTER TABLE api_keys ADD COLUMN rate_limit_per_minute INTEGER;
+
+-- One bucket per API key ('key:<id>') or client address ('ip:<addr>').
+-- tokens refill continuously at capacity per minute; updated_at is in fractional Unix seconds.
+-- A bucket idle for a minute is full again, so such rows are deleted opportunistically.
+CREATE TABLE rate_limit_buckets (
+    bucket TEXT PRIMARY KEY,
+    capacity INTEGER NOT NULL,
+    tokens REAL NOT NULL,
+    allowed INTEGER NOT NULL,
+    updated_at REAL NOT NULL
+);
+
+CREATE INDEX idx_rate_limit_buckets_updated_at ON rate_limit_buckets(updated_at);
+
+INSERT INTO schema_version (version, name, applied_at)
+VALUES (13, '0013_rate_limits', CAST(strftime('%s', 'now') AS INTEGER));
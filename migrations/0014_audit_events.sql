@@ -0,0 +1,26 @@
+-- Migration: Audit trail for security and data events
+-- Created: 2025-09-28
+
+-- One row per recorded event, newest last. id is the rowid, so it also orders events that
+-- share a created_at second. There are deliberately no foreign keys: events must outlive the
+-- keys and todos they mention.
+CREATE TABLE audit_events (
+    id INTEGER PRIMARY KEY,
+    event_type TEXT NOT NULL,       -- e.g. 'key.created', 'auth.failed', 'todo.updated'
+    actor_key_id TEXT,              -- NULL for unauthenticated requests
+    target_id TEXT,                 -- The key or todo the event is about
+    client_ip TEXT,
+    method TEXT,
+    path TEXT,
+    request_id TEXT,
+    changes TEXT,                   -- JSON field diff: {"field": {"from": ..., "to": ...}}
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX idx_audit_events_event_type ON audit_events(event_type, id);
+CREATE INDEX idx_audit_events_actor ON audit_events(actor_key_id, id);
+CREATE INDEX idx_audit_events_target ON audit_events(target_id, id);
+CREATE INDEX idx_audit_events_created_at ON audit_events(created_at);
+
+INSERT INTO schema_version (version, name, applied_at)
+VALUES (14, '0014_audit_events', CAST(strftime('%s', 'now') AS INTEGER));
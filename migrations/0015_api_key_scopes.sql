@@ -0,0 +1,9 @@
+-- Migration: Fine-grained API key scopes
+-- Created: 2025-09-30
+
+-- Space-separated scopes such as 'todos:read todos:write'.
+-- NULL means the preset for the key's key_type, which is what every existing key gets.
+ALTER TABLE api_keys ADD COLUMN scopes TEXT;
+
+INSERT INTO schema_version (version, name, applied_at)
+VALUES (15, '0015_api_key_scopes', CAST(strftime('%s', 'now') AS INTEGER));
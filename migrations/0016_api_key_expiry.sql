@@ -0,0 +1,8 @@
+-- Migration: Optional API key expiry
+-- Created: 2025-10-02
+
+-- Unix timestamp after which the key stops authenticating; NULL keys never expire
+ALTER TABLE api_keys ADD COLUMN expires_at INTEGER;
+
+INSERT INTO schema_version (version, name, applied_at)
+VALUES (16, '0016_api_key_expiry', CAST(strftime('%s', 'now') AS INTEGER));
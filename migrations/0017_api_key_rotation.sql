@@ -0,0 +1,13 @@
+-- Migration: API key rotation with an overlap window
+-- Created: 2025-10-04
+
+-- owner_key_id: the key whose todos and tags this key works on; NULL means its own.
+-- A successor inherits its predecessor's owner, so rotation never moves any rows.
+ALTER TABLE api_keys ADD COLUMN owner_key_id TEXT;
+-- rotated_from: the key this one replaced; replaced_by: the successor issued for this key.
+-- A replaced key stays valid until its expires_at (the grace period), then is deactivated.
+ALTER TABLE api_keys ADD COLUMN rotated_from TEXT;
+ALTER TABLE api_keys ADD COLUMN replaced_by TEXT;
+
+INSERT INTO schema_version (version, name, applied_at)
+VALUES (17, '0017_api_key_rotation', CAST(strftime('%s', 'now') AS INTEGER));
@@ -0,0 +1,11 @@
+-- Migration: Revocation time for API keys
+-- Created: 2025-10-06
+
+-- When the key was deactivated; NULL while it is active. Purging uses it to find old revoked keys.
+ALTER TABLE api_keys ADD COLUMN revoked_at INTEGER;
+
+-- Keys revoked before this migration: the last time they were seen is the best estimate
+UPDATE api_keys SET revoked_at = COALESCE(last_used, created_at) WHERE active = 0;
+
+INSERT INTO schema_version (version, name, applied_at)
+VALUES (18, '0018_api_key_revoked_at', CAST(strftime('%s', 'now') AS INTEGER));
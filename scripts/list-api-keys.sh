@@ -20,7 +20,8 @@ wrangler d1 execute pali-database $WRANGLER_FLAGS --command="
 SELECT 
   client_name,
   key_type,
-  SUBSTR(key_hash, 1, 8) || '...' as key_preview,
+  lookup_id,
+  CASE WHEN key_hash LIKE 'pbkdf2-sha256$%' THEN 'pbkdf2-sha256' ELSE 'legacy' END as hash_format,
   datetime(created_at, 'unixepoch') as created_at,
   datetime(last_used, 'unixepoch') as last_used,
   CASE WHEN active = 1 THEN 'ACTIVE' ELSE 'INACTIVE' END as status
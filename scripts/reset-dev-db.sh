@@ -20,7 +20,18 @@ echo "Resetting local development database..."
 # Drop existing tables and migration history
 echo "1. Dropping existing tables and migration state..."
 wrangler d1 execute pali-database --local --command="
+DROP TABLE IF EXISTS audit_events;
+DROP TABLE IF EXISTS rate_limit_buckets;
+DROP TABLE IF EXISTS schema_version;
+DROP TABLE IF EXISTS idempotency_keys;
+DROP TABLE IF EXISTS write_preconditions;
+DROP TABLE IF EXISTS todo_tombstones;
+DROP TABLE IF EXISTS change_counter;
+DROP TABLE IF EXISTS todos_fts;
+DROP TABLE IF EXISTS todo_tags;
+DROP TABLE IF EXISTS tags;
 DROP TABLE IF EXISTS todos; 
+DROP TABLE IF EXISTS recovery_attempts;
 DROP TABLE IF EXISTS api_keys;
 DROP TABLE IF EXISTS d1_migrations;
 "
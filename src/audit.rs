@@ -0,0 +1,154 @@
+// Audit trail of security and data events (audit_events, migration 0014), served by GET /admin/audit
+// Handlers record what they changed once the change is made. Recording is best-effort: a failed
+// write is logged but never fails the request, whose change has already been applied by then.
+// Events older than AUDIT_RETENTION_DAYS (a [vars] entry, default 365) are dropped as new ones arrive.
+
+#[allow(clippy::wildcard_imports)]
+use crate::platform::*;
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+use crate::auth::client_ip;
+use crate::db::Database;
+use crate::middleware::RequestInfo;
+use crate::models::{AuditEvent, TodoResponse};
+
+const RETENTION_DAYS_VAR: &str = "AUDIT_RETENTION_DAYS";
+const DEFAULT_RETENTION_DAYS: i64 = 365;
+
+// Todo fields whose changes are recorded; ids, timestamps and versions are implied by the event
+const TODO_AUDIT_FIELDS: &[&str] = &["title", "description", "completed", "priority", "due_date", "tags"];
+
+// What happened, stored as its dotted name in audit_events.event_type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    ServerInitialized,
+    ServerReinitialized,
+    AuthFailed,
+    KeyCreated,
+    KeyUpdated,
+    KeyRotated,
+    KeyRevoked,
+    KeyPurged,
+    TodoCreated,
+    TodoUpdated,
+    TodoDeleted,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 11] = [
+        AuditAction::ServerInitialized,
+        AuditAction::ServerReinitialized,
+        AuditAction::AuthFailed,
+        AuditAction::KeyCreated,
+        AuditAction::KeyUpdated,
+        AuditAction::KeyRotated,
+        AuditAction::KeyRevoked,
+        AuditAction::KeyPurged,
+        AuditAction::TodoCreated,
+        AuditAction::TodoUpdated,
+        AuditAction::TodoDeleted,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::ServerInitialized => "server.initialized",
+            AuditAction::ServerReinitialized => "server.reinitialized",
+            AuditAction::AuthFailed => "auth.failed",
+            AuditAction::KeyCreated => "key.created",
+            AuditAction::KeyUpdated => "key.updated",
+            AuditAction::KeyRotated => "key.rotated",
+            AuditAction::KeyRevoked => "key.revoked",
+            AuditAction::KeyPurged => "key.purged",
+            AuditAction::TodoCreated => "todo.created",
+            AuditAction::TodoUpdated => "todo.updated",
+            AuditAction::TodoDeleted => "todo.deleted",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.as_str() == value)
+    }
+}
+
+// Who made the request and where it came from, shared by every event the request records
+pub struct AuditContext {
+    actor_key_id: Option<String>,
+    client_ip: String,
+    method: String,
+    path: String,
+    request_id: String,
+}
+
+impl AuditContext {
+    // actor_key_id is None for requests without a valid API key
+    pub fn new(req: &Request, info: &RequestInfo, actor_key_id: Option<&str>) -> Self {
+        Self {
+            actor_key_id: actor_key_id.map(str::to_string),
+            client_ip: client_ip(req),
+            method: req.method().to_string(),
+            path: req.path(),
+            request_id: info.request_id.clone(),
+        }
+    }
+
+    pub fn event(&self, action: AuditAction, target_id: Option<&str>, changes: Option<Value>) -> AuditEvent {
+        AuditEvent {
+            id: 0,
+            event_type: action.as_str().to_string(),
+            actor_key_id: self.actor_key_id.clone(),
+            target_id: target_id.map(str::to_string),
+            client_ip: Some(self.client_ip.clone()),
+            method: Some(self.method.clone()),
+            path: Some(self.path.clone()),
+            request_id: Some(self.request_id.clone()),
+            changes,
+            created_at: Utc::now().timestamp(),
+        }
+    }
+
+    pub async fn record(&self, db: &Database, env: &Env, action: AuditAction, target_id: Option<&str>, changes: Option<Value>) {
+        record_all(db, env, vec![self.event(action, target_id, changes)]).await;
+    }
+}
+
+fn retention_secs(env: &Env) -> i64 {
+    env.var(RETENTION_DAYS_VAR)
+        .ok()
+        .and_then(|value| value.to_string().parse::<i64>().ok())
+        .filter(|days| *days > 0)
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+        * 24 * 60 * 60
+}
+
+// Store events from one request together
+pub async fn record_all(db: &Database, env: &Env, events: Vec<AuditEvent>) {
+    if events.is_empty() {
+        return;
+    }
+
+    let expires_before = Utc::now().timestamp() - retention_secs(env);
+    if let Err(e) = db.record_audit_events(&events, expires_before).await {
+        let request_id = events[0].request_id.as_deref().unwrap_or("unknown");
+        console_log!("AUDIT FAILED: {} event(s) not recorded - {} (request {})", events.len(), e, request_id);
+    }
+}
+
+// Field diff between two JSON objects: {"field": {"from": before, "to": after}} for each field
+// in `fields` whose value differs; a missing side counts as null
+pub fn changes(before: Option<&Value>, after: Option<&Value>, fields: &[&str]) -> Value {
+    let field = |object: Option<&Value>, name: &str| object.and_then(|object| object.get(name)).cloned().unwrap_or(Value::Null);
+
+    let diff: Map<String, Value> = fields.iter()
+        .filter_map(|name| {
+            let (from, to) = (field(before, name), field(after, name));
+            (from != to).then(|| (name.to_string(), json!({ "from": from, "to": to })))
+        })
+        .collect();
+    Value::Object(diff)
+}
+
+// Diff of a todo's user-visible fields; None on one side records a create or delete
+pub fn todo_changes(before: Option<&TodoResponse>, after: Option<&TodoResponse>) -> Value {
+    let to_value = |todo: Option<&TodoResponse>| todo.and_then(|todo| serde_json::to_value(todo).ok());
+    changes(to_value(before).as_ref(), to_value(after).as_ref(), TODO_AUDIT_FIELDS)
+}
@@ -1,36 +1,220 @@
 // API key authentication logic for Pali server
 // Authentication is fully integrated into all handlers
-// TODO: Add rate limiting per API key
 // TODO: Implement key usage analytics/metrics
 
-use worker::*;
-use crate::models::{KeyType, hash_api_key};
-use crate::db::Database;
+#[allow(clippy::wildcard_imports)]
+use crate::platform::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use chrono::Utc;
+use crate::models::{
+    KeyType, Scope, hash_api_key, legacy_hash_api_key, verify_api_key, needs_rehash,
+    api_key_lookup_id, is_legacy_api_key, constant_time_eq,
+};
+use crate::db::{Database, TodoScope};
+use sha2::{Digest, Sha256};
+
+// Worker secret holding the break-glass credential for POST /reinitialize
+// Set with: wrangler secret put RECOVERY_SECRET
+pub const RECOVERY_SECRET_BINDING: &str = "RECOVERY_SECRET";
+
+// How long a rotated key keeps working next to its successor, unless the rotation asks otherwise
+// Override with ROTATION_GRACE_SECS in [vars]; requests may ask for up to MAX_ROTATION_GRACE_SECS
+const ROTATION_GRACE_VAR: &str = "ROTATION_GRACE_SECS";
+const DEFAULT_ROTATION_GRACE_SECS: i64 = 24 * 60 * 60;
+pub const MAX_ROTATION_GRACE_SECS: i64 = 30 * 24 * 60 * 60;
 
 #[derive(Clone)]
 pub struct AuthContext {
+    pub key_id: String,
     pub key_type: KeyType,
     pub client_name: String,
+    pub scopes: Vec<Scope>,
+    // Key whose todos this key works on; differs from key_id once a key has been rotated
+    pub owner_key_id: String,
+}
+
+// Verified keys are cached per isolate so repeat requests skip PBKDF2 and the D1 round-trips
+// Revocation clears matching entries in this isolate immediately; other isolates
+// stop accepting a revoked key once their entry expires (at most AUTH_CACHE_TTL_SECS)
+// Entries never outlive the key's own expires_at
+const AUTH_CACHE_TTL_SECS: i64 = 30;
+const AUTH_CACHE_MAX_ENTRIES: usize = 1024;
+
+struct CachedAuth {
+    auth: AuthContext,
+    expires_at: i64,
+}
+
+thread_local! {
+    // Keyed by a fast SHA-256 of the presented key, never the raw key
+    static AUTH_CACHE: RefCell<HashMap<String, CachedAuth>> = RefCell::new(HashMap::new());
+}
+
+fn auth_cache_key(api_key: &str) -> String {
+    hex::encode(Sha256::digest(api_key.as_bytes()))
+}
+
+fn cached_auth(cache_key: &str, now: i64) -> Option<AuthContext> {
+    AUTH_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        match cache.get(cache_key) {
+            Some(entry) if entry.expires_at > now => Some(entry.auth.clone()),
+            Some(_) => {
+                cache.remove(cache_key);
+                None
+            },
+            None => None,
+        }
+    })
+}
+
+fn cache_auth(cache_key: String, auth: AuthContext, now: i64, key_expires_at: Option<i64>) {
+    AUTH_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        
+        // Keep memory bounded: drop expired entries first, then everything if still full
+        if cache.len() >= AUTH_CACHE_MAX_ENTRIES {
+            cache.retain(|_, entry| entry.expires_at > now);
+            if cache.len() >= AUTH_CACHE_MAX_ENTRIES {
+                cache.clear();
+            }
+        }
+        
+        let expires_at = key_expires_at.map_or(now + AUTH_CACHE_TTL_SECS, |key_expires_at| key_expires_at.min(now + AUTH_CACHE_TTL_SECS));
+        cache.insert(cache_key, CachedAuth { auth, expires_at });
+    });
+}
+
+// Drop a revoked key from this isolate's cache
+pub fn invalidate_cached_key(key_id: &str) {
+    AUTH_CACHE.with(|cache| cache.borrow_mut().retain(|_, entry| entry.auth.key_id != key_id));
+}
+
+// Drop every cached admin key (used after emergency reinitialization)
+pub fn invalidate_cached_admin_keys() {
+    AUTH_CACHE.with(|cache| cache.borrow_mut().retain(|_, entry| entry.auth.key_type != KeyType::Admin));
 }
 
 // Helper function to validate API key from request headers
 // Integrated into all protected handlers
 pub async fn validate_api_key_from_request(req: &Request, env: &Env) -> Option<AuthContext> {
     let api_key = req.headers().get("X-API-Key").ok()??;
-    let key_hash = hash_api_key(&api_key);
+    let now = Utc::now().timestamp();
+    let cache_key = auth_cache_key(&api_key);
+    
+    if let Some(auth) = cached_auth(&cache_key, now) {
+        return Some(auth);
+    }
     
     let d1 = env.d1("DB").ok()?;
     let db = Database::new(d1);
     
-    let key_info = db.validate_api_key(&key_hash).await.ok()??;
+    let lookup_id = api_key_lookup_id(&api_key);
+    let key_info = match db.find_api_key_by_lookup_id(&lookup_id).await.ok()? {
+        // Fetch the row by lookup id, then verify the secret against whichever hash version it stores
+        Some(key_info) => {
+            if !verify_api_key(&api_key, &key_info.key_hash) {
+                return None;
+            }
+            if needs_rehash(&key_info.key_hash) {
+                db.update_api_key_hash(&key_info.id, &hash_api_key(&api_key), &lookup_id).await.ok()?;
+            }
+            db.touch_api_key(&key_info.id).await.ok()?;
+            key_info
+        },
+        // Legacy keys whose row predates lookup ids: match the unsalted hash once, then upgrade
+        None if is_legacy_api_key(&api_key) => {
+            let key_info = db.validate_api_key(&legacy_hash_api_key(&api_key)).await.ok()??;
+            db.update_api_key_hash(&key_info.id, &hash_api_key(&api_key), &lookup_id).await.ok()?;
+            key_info
+        },
+        None => return None,
+    };
     
-    Some(AuthContext {
+    let key_expires_at = key_info.expires_at;
+    let owner_key_id = key_info.todo_owner().to_string();
+    let auth = AuthContext {
+        key_id: key_info.id,
         key_type: key_info.key_type,
         client_name: key_info.client_name,
-    })
+        scopes: key_info.scopes,
+        owner_key_id,
+    };
+    
+    // last_used is therefore refreshed on cache misses only (at most once per TTL per isolate)
+    cache_auth(cache_key, auth.clone(), now, key_expires_at);
+    Some(auth)
+}
+
+// Whether the key may perform actions guarded by `scope`
+pub fn has_scope(auth: &AuthContext, scope: Scope) -> bool {
+    auth.scopes.contains(&scope)
+}
+
+// Resolve which owners' todos a request may touch
+// Keys with todos:all_owners (admins by default) can opt into cross-owner queries with
+// ?all_owners=true; other keys are always scoped to themselves
+pub fn todo_scope(req: &Request, auth: &AuthContext) -> TodoScope {
+    let all_owners = req.url()
+        .map(|url| url.query_pairs().any(|(key, value)| key == "all_owners" && value == "true"))
+        .unwrap_or(false);
+    
+    if all_owners && has_scope(auth, Scope::TodosAllOwners) {
+        TodoScope::AllOwners
+    } else {
+        TodoScope::Owner(auth.owner_key_id.clone())
+    }
+}
+
+// Best-effort client address for throttling and logs (set by Cloudflare on every request)
+pub fn client_ip(req: &Request) -> String {
+    req.headers()
+        .get("CF-Connecting-IP")
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| "unknown".to_string())
 }
 
-// Helper to check if auth context has admin privileges
-pub fn is_admin(auth: &AuthContext) -> bool {
-    auth.key_type == KeyType::Admin
-}
\ No newline at end of file
+// Outcome of checking the X-Recovery-Secret header against the configured secret
+pub enum RecoveryCheck {
+    Valid,
+    Invalid,
+    NotConfigured,
+}
+
+// Verify the break-glass secret for emergency reinitialization
+// Digests are compared in constant time so response timing does not leak the secret
+pub fn verify_recovery_secret(req: &Request, env: &Env) -> RecoveryCheck {
+    let expected = match env.secret(RECOVERY_SECRET_BINDING) {
+        Ok(secret) => secret.to_string(),
+        Err(_) => return RecoveryCheck::NotConfigured,
+    };
+    
+    // An empty secret would make the endpoint effectively unauthenticated
+    if expected.is_empty() {
+        return RecoveryCheck::NotConfigured;
+    }
+    
+    let provided = match req.headers().get("X-Recovery-Secret").ok().flatten() {
+        Some(provided) => provided,
+        None => return RecoveryCheck::Invalid,
+    };
+    
+    let expected_digest = Sha256::digest(expected.as_bytes());
+    let provided_digest = Sha256::digest(provided.as_bytes());
+    
+    if constant_time_eq(&expected_digest, &provided_digest) {
+        RecoveryCheck::Valid
+    } else {
+        RecoveryCheck::Invalid
+    }
+}
+
+pub fn rotation_grace_secs(env: &Env) -> i64 {
+    env.var(ROTATION_GRACE_VAR)
+        .ok()
+        .and_then(|value| value.to_string().parse::<i64>().ok())
+        .filter(|secs| (0..=MAX_ROTATION_GRACE_SECS).contains(secs))
+        .unwrap_or(DEFAULT_ROTATION_GRACE_SECS)
+}
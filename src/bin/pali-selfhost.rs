@@ -0,0 +1,144 @@
+// Self-hosted Pali server: the Worker's routes served over plain HTTP from a local SQLite file
+// Build with: cargo build --release --features server --bin pali-selfhost
+//
+// Configuration comes from environment variables:
+//   PALI_LISTEN            address to listen on (default 127.0.0.1:8787)
+//   PALI_DB                SQLite database file, created if missing (default pali.db)
+//   PALI_THREADS           request handling threads (default 4)
+//   PALI_CLIENT_IP_HEADER  header carrying the client address when behind a reverse proxy
+//                          (e.g. X-Real-IP); without it the socket peer address is used
+//   PALI_MAX_BODY_BYTES    largest request body accepted, larger ones get 413 (default 1 MiB)
+//   RECOVERY_SECRET and every optional [vars] entry listed in wrangler.toml (IDEMPOTENCY_WINDOW_SECS,
+//   TODO_MAX_*, RATE_LIMIT_*_PER_MINUTE, AUDIT_RETENTION_DAYS, ROTATION_GRACE_SECS, ...) are read
+//   from the environment with the same meaning as for the Worker
+
+use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+use pali_server::db::Database;
+use pali_server::native::{Env, Headers, Method, Request, Response, Url};
+use pali_server::schema;
+use pali_server::storage::{block_on, SqliteStorage};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8787";
+const DEFAULT_DB_PATH: &str = "pali.db";
+const DEFAULT_THREADS: usize = 4;
+// Cloudflare caps Worker request bodies; nothing else would here
+const DEFAULT_MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("pali-selfhost: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let listen = std::env::var("PALI_LISTEN").unwrap_or_else(|_| DEFAULT_LISTEN.to_string());
+    let db_path = std::env::var("PALI_DB").unwrap_or_else(|_| DEFAULT_DB_PATH.to_string());
+    let threads = std::env::var("PALI_THREADS").ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|threads| *threads > 0)
+        .unwrap_or(DEFAULT_THREADS);
+    let client_ip_header = std::env::var("PALI_CLIENT_IP_HEADER").ok();
+    let max_body_bytes = std::env::var("PALI_MAX_BODY_BYTES").ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|bytes| *bytes > 0)
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES);
+
+    let storage = Arc::new(SqliteStorage::open(&db_path).map_err(|e| format!("Cannot open {}: {}", db_path, e))?);
+
+    // Bring the schema up to date before serving, so requests never see the 503 schema guard
+    let applied = block_on(schema::migrate(&Database::with_storage(Arc::clone(&storage))))
+        .map_err(|e| e.to_string())?;
+    if !applied.is_empty() {
+        println!("Applied {} migration(s) to {}", applied.len(), db_path);
+    }
+
+    let server = Arc::new(tiny_http::Server::http(&listen).map_err(|e| format!("Cannot listen on {}: {}", listen, e))?);
+    println!("Pali server listening on http://{} (database: {})", listen, db_path);
+
+    let env = Env::new(storage);
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let env = env.clone();
+            let client_ip_header = client_ip_header.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    serve(request, &env, client_ip_header.as_deref(), max_body_bytes);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().map_err(|_| "Request thread panicked".to_string())?;
+    }
+    Ok(())
+}
+
+fn serve(mut request: tiny_http::Request, env: &Env, client_ip_header: Option<&str>, max_body_bytes: u64) {
+    let response = match to_request(&mut request, client_ip_header, max_body_bytes) {
+        Ok(req) => block_on(pali_server::handle(req, env.clone())),
+        Err((message, status)) => Response::error(message, status),
+    };
+
+    let response = response.unwrap_or_else(|e| {
+        eprintln!("{} {}: {}", request.method(), request.url(), e);
+        Response::error("Internal Server Error", 500).expect("plain text response")
+    });
+
+    if let Err(e) = request.respond(to_tiny_response(&response)) {
+        eprintln!("Failed to send response: {}", e);
+    }
+}
+
+// Errors carry the status to answer with: 400 for malformed requests, 413 for oversized bodies
+fn to_request(request: &mut tiny_http::Request, client_ip_header: Option<&str>, max_body_bytes: u64) -> Result<Request, (String, u16)> {
+    let bad_request = |message: String| (message, 400);
+    let too_large = || (format!("Request body exceeds {} bytes", max_body_bytes), 413);
+    
+    if request.body_length().is_some_and(|length| length as u64 > max_body_bytes) {
+        return Err(too_large());
+    }
+    
+    let mut headers = Headers::new();
+    for header in request.headers() {
+        headers.append(header.field.as_str().as_str(), header.value.as_str()).map_err(|e| bad_request(e.to_string()))?;
+    }
+
+    // The handlers read the client address from CF-Connecting-IP, which Cloudflare always sets;
+    // here it is derived from the connection so clients cannot choose their own
+    let forwarded = client_ip_header
+        .and_then(|name| headers.get(name).ok().flatten())
+        .and_then(|value| value.split(',').next().map(|ip| ip.trim().to_string()));
+    let client_ip = forwarded
+        .or_else(|| request.remote_addr().map(|addr| addr.ip().to_string()))
+        .unwrap_or_else(|| "unknown".to_string());
+    headers.set("CF-Connecting-IP", &client_ip).map_err(|e| bad_request(e.to_string()))?;
+
+    // Handlers only use the path and query, so the client's Host header is not trusted to parse
+    let url = Url::parse(&format!("http://localhost{}", request.url())).map_err(|e| bad_request(format!("Invalid request URL: {}", e)))?;
+
+    // Chunked bodies have no Content-Length, so the read itself is capped as well
+    let mut body = Vec::new();
+    request.as_reader().take(max_body_bytes + 1).read_to_end(&mut body)
+        .map_err(|e| bad_request(format!("Failed to read request body: {}", e)))?;
+    if body.len() as u64 > max_body_bytes {
+        return Err(too_large());
+    }
+
+    Ok(Request::new(Method::from(request.method().as_str().to_string()), url, headers, body))
+}
+
+fn to_tiny_response(response: &Response) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let mut tiny = tiny_http::Response::from_data(response.body().to_vec())
+        .with_status_code(response.status_code());
+    for (name, value) in response.headers().entries() {
+        if let Ok(header) = tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+            tiny.add_header(header);
+        }
+    }
+    tiny
+}
@@ -1,14 +1,15 @@
-// Database operations and D1 integration for Pali server
+// Database operations for Pali server, written once against the Storage trait (see storage.rs)
 // TODO: Add connection pooling if/when D1 supports it
-// TODO: Implement proper transaction support
-// TODO: Add database migration system
 
 use worker::*;
-use wasm_bindgen::JsValue;
-use crate::models::{Todo, ApiKey, KeyType, CreateTodoRequest, UpdateTodoRequest};
+use crate::models::{Todo, TodoResponse, SearchResult, SearchHighlights, SyncResponse, Tombstone, TagInfo, TodoPatch, BatchOperation, BatchOperationResult, BatchResponse, BatchStatus, ApiKey, AuditEvent, KeyType, Scope, parse_scopes, format_scopes, CreateTodoRequest, UpdateTodoRequest};
+use crate::pagination::{Page, PageRequest};
+use crate::storage::{PlatformStorage, SqlValue, Statement, StatementResult, Storage};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 
 // WORKAROUND: D1 serialization issue with booleans
 // Issue: https://github.com/cloudflare/workers-rs/issues/387
@@ -26,6 +27,10 @@ struct TodoRow {
     due_date: Option<i64>,
     created_at: i64,
     updated_at: i64,
+    owner_key_id: Option<String>,
+    #[serde(default)]
+    change_seq: i64,  // Maintained by triggers (migration 0008)
+    version: i64,
 }
 
 impl From<TodoRow> for Todo {
@@ -43,6 +48,18 @@ impl From<TodoRow> for Todo {
     }
 }
 
+// Tags are loaded separately (see attach_tags)
+impl From<TodoRow> for TodoResponse {
+    fn from(row: TodoRow) -> Self {
+        let version = row.version;
+        TodoResponse {
+            todo: row.into(),
+            version,
+            tags: Vec::new(),
+        }
+    }
+}
+
 // WORKAROUND: ApiKey serialization pattern (same D1 boolean issue)
 // Apply same workaround pattern as TodoRow for consistent handling
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,25 +71,73 @@ struct ApiKeyRow {
     last_used: Option<i64>,
     created_at: i64,
     active: i32,  // 0 = false, 1 = true (D1 limitation)
+    // Columns added by later migrations are optional, so the lookups used for authentication
+    // (SELECT *) keep working on an older schema, e.g. to reach POST /admin/schema/migrate
+    #[serde(default)]
+    rate_limit_per_minute: Option<i64>,
+    #[serde(default)]
+    scopes: Option<String>,  // NULL = preset for key_type (migration 0015)
+    #[serde(default)]
+    expires_at: Option<i64>,  // NULL = never expires (migration 0016)
+    #[serde(default)]
+    owner_key_id: Option<String>,  // Rotation links (migration 0017)
+    #[serde(default)]
+    rotated_from: Option<String>,
+    #[serde(default)]
+    replaced_by: Option<String>,
+    #[serde(default)]
+    revoked_at: Option<i64>,  // NULL while active (migration 0018)
 }
 
 impl From<ApiKeyRow> for ApiKey {
     fn from(row: ApiKeyRow) -> Self {
+        let key_type = match row.key_type.to_lowercase().as_str() {
+            "admin" => KeyType::Admin,
+            _ => KeyType::Client,  // Default to Client for any non-admin type
+        };
+        let scopes = match &row.scopes {
+            Some(scopes) => parse_scopes(scopes),
+            None => Scope::preset(&key_type),
+        };
+        
         ApiKey {
             id: row.id,
             key_hash: row.key_hash,
             client_name: row.client_name,
-            key_type: match row.key_type.to_lowercase().as_str() {
-                "admin" => KeyType::Admin,
-                _ => KeyType::Client,  // Default to Client for any non-admin type
-            },
+            key_type,
             last_used: row.last_used,
             created_at: row.created_at,
             active: row.active != 0,  // Convert i32 to bool
+            rate_limit_per_minute: row.rate_limit_per_minute.and_then(|limit| u32::try_from(limit).ok()),
+            scopes,
+            expires_at: row.expires_at,
+            owner_key_id: row.owner_key_id,
+            rotated_from: row.rotated_from,
+            replaced_by: row.replaced_by,
+            revoked_at: row.revoked_at,
         }
     }
 }
 
+// Revoked key considered by purge_api_keys
+#[derive(Debug, Serialize, Deserialize)]
+struct PurgeCandidateRow {
+    id: String,
+    owns_todos: i32,  // 0 = false, 1 = true (D1 limitation)
+}
+
+// Row struct for queries that only need ids
+#[derive(Debug, Serialize, Deserialize)]
+struct IdRow {
+    id: String,
+}
+
+// Expiry a rotated key was given (RETURNING row)
+#[derive(Debug, Serialize, Deserialize)]
+struct ExpiresAtRow {
+    expires_at: i64,
+}
+
 // Row struct for ID resolution queries (consistent with other D1 patterns)
 #[derive(Debug, Serialize, Deserialize)]
 struct IdTitleRow {
@@ -80,13 +145,465 @@ struct IdTitleRow {
     title: String,
 }
 
-pub struct Database {
-    d1: D1Database,
+// Row struct for full-text search hits: todo columns plus bm25 score and highlighted fragments
+// (kept flat rather than embedding TodoRow, since serde(flatten) defeats the D1 number coercion)
+#[derive(Debug, Serialize, Deserialize)]
+struct SearchRow {
+    id: String,
+    title: String,
+    description: Option<String>,
+    completed: i32,  // 0 = false, 1 = true (D1 limitation)
+    priority: i32,
+    due_date: Option<i64>,
+    created_at: i64,
+    updated_at: i64,
+    version: i64,
+    score: f64,
+    title_highlight: String,
+    description_snippet: Option<String>,
+}
+
+impl SearchRow {
+    fn into_parts(self) -> (TodoResponse, f64, SearchHighlights) {
+        let todo = Todo {
+            id: self.id,
+            title: self.title,
+            description: self.description,
+            completed: self.completed != 0,
+            priority: self.priority,
+            due_date: self.due_date,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        };
+        let todo = TodoResponse { todo, version: self.version, tags: Vec::new() };
+        let highlights = SearchHighlights {
+            title: self.title_highlight,
+            description: self.description_snippet.filter(|snippet| !snippet.is_empty()),
+        };
+        (todo, self.score, highlights)
+    }
+}
+
+// Row struct for batch precondition reads
+#[derive(Debug, Serialize, Deserialize)]
+struct IdVersionRow {
+    id: String,
+    version: i64,
+}
+
+// Stored outcome of a request made with an Idempotency-Key
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub request_hash: String,
+    pub status_code: Option<u16>,  // None while the original request is in flight
+    pub response_headers: Option<String>,
+    pub response_body: Option<String>,
+}
+
+// Token bucket state right after taking (or failing to take) a token
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimitBucket {
+    pub capacity: i64,
+    pub tokens: f64,
+    pub allowed: i32,  // 0 = false, 1 = true (D1 limitation)
+}
+
+// Row struct for audit_events; changes is stored as JSON text
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditEventRow {
+    id: i64,
+    event_type: String,
+    actor_key_id: Option<String>,
+    target_id: Option<String>,
+    client_ip: Option<String>,
+    method: Option<String>,
+    path: Option<String>,
+    request_id: Option<String>,
+    changes: Option<String>,
+    created_at: i64,
+}
+
+impl From<AuditEventRow> for AuditEvent {
+    fn from(row: AuditEventRow) -> Self {
+        AuditEvent {
+            id: row.id,
+            event_type: row.event_type,
+            actor_key_id: row.actor_key_id,
+            target_id: row.target_id,
+            client_ip: row.client_ip,
+            method: row.method,
+            path: row.path,
+            request_id: row.request_id,
+            changes: row.changes.and_then(|changes| serde_json::from_str(&changes).ok()),
+            created_at: row.created_at,
+        }
+    }
+}
+
+// Row struct for deletion tombstones
+#[derive(Debug, Serialize, Deserialize)]
+struct TombstoneRow {
+    todo_id: String,
+    change_seq: i64,
+    deleted_at: i64,
+}
+
+// Row struct for tag lookups joined through todo_tags
+#[derive(Debug, Serialize, Deserialize)]
+struct TodoTagRow {
+    todo_id: String,
+    name: String,
+}
+
+// How multiple ?tag= filters combine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TagMatch {
+    #[default]
+    All,
+    Any,
+}
+
+// Filters accepted by list_todos; every field is optional and they combine with AND
+// Range bounds are inclusive for *_after / *_min and exclusive for *_before, *_max is inclusive
+#[derive(Debug, Clone, Default)]
+pub struct TodoFilter {
+    pub completed: Option<bool>,
+    pub priority: Option<i32>,
+    pub priority_min: Option<i32>,
+    pub priority_max: Option<i32>,
+    pub due_after: Option<i64>,
+    pub due_before: Option<i64>,
+    pub overdue: bool,
+    pub due_today: bool,
+    pub tz_offset_minutes: i64,  // Defines "today" for due_today (minutes east of UTC)
+    pub created_after: Option<i64>,
+    pub created_before: Option<i64>,
+    pub updated_after: Option<i64>,
+    pub updated_before: Option<i64>,
+    pub has_description: Option<bool>,
+    pub tags: Vec<String>,
+    pub tag_match: TagMatch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Priority,
+    DueDate,
+    CreatedAt,
+    UpdatedAt,
+    Title,
+}
+
+impl SortField {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "priority" => Some(SortField::Priority),
+            "due_date" => Some(SortField::DueDate),
+            "created_at" => Some(SortField::CreatedAt),
+            "updated_at" => Some(SortField::UpdatedAt),
+            "title" => Some(SortField::Title),
+            _ => None,
+        }
+    }
+    
+    // Natural direction when ?order= is omitted: soonest due dates and A-Z titles first
+    pub fn default_direction(self) -> SortDirection {
+        match self {
+            SortField::DueDate | SortField::Title => SortDirection::Asc,
+            _ => SortDirection::Desc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    fn sql(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+    
+    // Comparison that selects rows after the cursor in this direction
+    fn after_operator(self) -> &'static str {
+        match self {
+            SortDirection::Asc => ">",
+            SortDirection::Desc => "<",
+        }
+    }
+}
+
+// Sort order for todo lists; the id is always the final tie-breaker so cursors are stable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoSort {
+    pub field: SortField,
+    pub direction: SortDirection,
+}
+
+impl Default for TodoSort {
+    fn default() -> Self {
+        Self { field: SortField::Priority, direction: SortDirection::Desc }
+    }
+}
+
+impl TodoSort {
+    // Todos without a due date always sort last, whichever the direction
+    // (largest magnitude that survives the f64 round-trip D1 bindings require)
+    const NO_DUE_DATE_LAST_ASC: i64 = 9_007_199_254_740_991;
+    const NO_DUE_DATE_LAST_DESC: i64 = -9_007_199_254_740_991;
+    
+    // (SQL expression, direction) pairs making up the ORDER BY, excluding the id tie-breaker
+    // Sorting by priority keeps the historical newest-first order within a priority
+    fn columns(&self) -> Vec<(String, SortDirection)> {
+        let primary = match self.field {
+            SortField::Priority => "priority".to_string(),
+            SortField::DueDate => format!("COALESCE(due_date, {})", self.missing_due_date()),
+            SortField::CreatedAt => "created_at".to_string(),
+            SortField::UpdatedAt => "updated_at".to_string(),
+            SortField::Title => "title COLLATE NOCASE".to_string(),
+        };
+        
+        let mut columns = vec![(primary, self.direction)];
+        if self.field == SortField::Priority {
+            columns.push(("created_at".to_string(), SortDirection::Desc));
+        }
+        columns
+    }
+    
+    fn missing_due_date(&self) -> i64 {
+        match self.direction {
+            SortDirection::Asc => Self::NO_DUE_DATE_LAST_ASC,
+            SortDirection::Desc => Self::NO_DUE_DATE_LAST_DESC,
+        }
+    }
+    
+    // Values of columns() for a todo, in the same order
+    fn key_values(&self, todo: &Todo) -> Vec<serde_json::Value> {
+        let primary = match self.field {
+            SortField::Priority => serde_json::json!(todo.priority),
+            SortField::DueDate => serde_json::json!(todo.due_date.unwrap_or(self.missing_due_date())),
+            SortField::CreatedAt => serde_json::json!(todo.created_at),
+            SortField::UpdatedAt => serde_json::json!(todo.updated_at),
+            SortField::Title => serde_json::json!(todo.title),
+        };
+        
+        let mut values = vec![primary];
+        if self.field == SortField::Priority {
+            values.push(serde_json::json!(todo.created_at));
+        }
+        values
+    }
+}
+
+// Keyset cursor for todo lists: the sort it was issued for plus the last row's sort key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TodoCursor {
+    sort: TodoSort,
+    keys: Vec<serde_json::Value>,
+    id: String,
+}
+
+impl TodoCursor {
+    fn after(sort: TodoSort, todo: &TodoResponse) -> Self {
+        Self {
+            sort,
+            keys: sort.key_values(&todo.todo),
+            id: todo.todo.id.clone(),
+        }
+    }
+    
+    // A cursor is only meaningful for the sort that produced it
+    pub fn matches(&self, sort: &TodoSort) -> bool {
+        self.sort == *sort && self.keys.len() == sort.columns().len()
+    }
+}
+
+// Push a bound parameter and return its ?N placeholder
+fn bind_param(params: &mut Vec<SqlValue>, value: SqlValue) -> String {
+    params.push(value);
+    format!("?{}", params.len())
+}
+
+// Cursor keys come back from JSON; bind numbers with their JSON type and strings as text
+fn json_to_param(value: &serde_json::Value) -> SqlValue {
+    match value {
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => integer.into(),
+            None => number.as_f64().unwrap_or_default().into(),
+        },
+        serde_json::Value::String(text) => text.as_str().into(),
+        _ => SqlValue::Null,
+    }
+}
+
+// Keyset cursor for API key lists, matching ORDER BY created_at DESC, id DESC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyCursor {
+    created_at: i64,
+    id: String,
+}
+
+impl ApiKeyCursor {
+    pub fn after(key: &ApiKey) -> Self {
+        Self {
+            created_at: key.created_at,
+            id: key.id.clone(),
+        }
+    }
+}
+
+// Optional settings for a new key; the defaults give the preset scopes for its type, the
+// default request budget and no expiry
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeyOptions {
+    pub scopes: Option<Vec<Scope>>,
+    pub rate_limit_per_minute: Option<u32>,
+    pub expires_at: Option<i64>,
+}
+
+// Keyset cursor for GET /admin/audit, matching ORDER BY id DESC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditCursor {
+    id: i64,
+}
+
+impl AuditCursor {
+    pub fn after(event: &AuditEvent) -> Self {
+        Self { id: event.id }
+    }
+}
+
+// Filters accepted by list_audit_events; every field is optional and they combine with AND
+// created_after is inclusive and created_before exclusive, as for todos
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub event_type: Option<String>,
+    pub actor_key_id: Option<String>,
+    pub target_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub created_after: Option<i64>,
+    pub created_before: Option<i64>,
+}
+
+// Keyset cursor for search results, matching ORDER BY score, id
+// score is the raw bm25 value (lower is more relevant); it round-trips exactly through JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchCursor {
+    score: f64,
+    id: String,
+}
+
+// Rewrite a query FTS5 rejected into plain terms: each whitespace-separated word becomes a
+// quoted string (a trailing * stays a prefix match), so operators and punctuation lose meaning
+fn literal_fts_query(query: &str) -> String {
+    query.split_whitespace()
+        .filter_map(|word| {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(stem) => (stem, "*"),
+                None => (word, ""),
+            };
+            let word = word.replace('"', "");
+            if word.trim_matches('*').is_empty() {
+                None
+            } else {
+                Some(format!("\"{}\"{}", word, prefix))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Ownership scope applied to every todo query
+// Owner limits results to a single API key's todos; AllOwners is reserved for admins
+#[derive(Debug, Clone)]
+pub enum TodoScope {
+    Owner(String),
+    AllOwners,
+}
+
+impl TodoScope {
+    // Bound as `(?N IS NULL OR owner_key_id = ?N)` so NULL disables the owner filter
+    fn owner_param(&self) -> SqlValue {
+        match self {
+            TodoScope::Owner(key_id) => key_id.as_str().into(),
+            TodoScope::AllOwners => SqlValue::Null,
+        }
+    }
+}
+
+// If-Match precondition for writes to an existing todo
+// Any covers both a missing header and "*"; OneOf lists the versions the client accepts
+#[derive(Debug, Clone, Default)]
+pub enum VersionMatch {
+    #[default]
+    Any,
+    OneOf(Vec<i64>),
+}
+
+impl VersionMatch {
+    // Bound as `(?N IS NULL OR version IN (SELECT value FROM json_each(?N)))`
+    fn param(&self) -> Result<SqlValue> {
+        match self {
+            VersionMatch::Any => Ok(SqlValue::Null),
+            VersionMatch::OneOf(versions) => Ok(serde_json::to_string(versions)?.into()),
+        }
+    }
+    
+    fn accepts(&self, version: i64) -> bool {
+        match self {
+            VersionMatch::Any => true,
+            VersionMatch::OneOf(versions) => versions.contains(&version),
+        }
+    }
+}
+
+// Outcome of a conditional todo write
+#[derive(Debug)]
+pub enum TodoWrite<T> {
+    Written(T),
+    NotFound,
+    VersionMismatch { current_version: i64 },
+}
+
+// Error raised by the write_preconditions trigger (migration 0010) to abort a batch
+const WRITE_PRECONDITION_FAILED: &str = "write precondition failed";
+
+// The owners whose data a reinitialization hands to the new key (?1): every active admin key
+// other than the new one, or the key it works on behalf of after a rotation
+const REINITIALIZED_OWNERS: &str = "SELECT COALESCE(owner_key_id, id) FROM api_keys WHERE key_type = 'admin' AND active = 1 AND id != ?1";
+
+// True when an admin key other than ?1 is active, unexpired at ?2 and holds keys:manage,
+// explicitly or through the admin preset; guards against locking out key management
+const OTHER_KEY_MANAGER_EXISTS: &str = "EXISTS (
+    SELECT 1 FROM api_keys other
+    WHERE other.id != ?1 AND other.key_type = 'admin' AND other.active = 1
+      AND (other.expires_at IS NULL OR other.expires_at > ?2)
+      AND (other.scopes IS NULL OR ' ' || other.scopes || ' ' LIKE '% keys:manage %')
+)";
+
+pub struct Database<S = PlatformStorage> {
+    storage: S,
 }
 
 impl Database {
-    pub fn new(d1: D1Database) -> Self {
-        Self { d1 }
+    // Wrap the platform's "DB" binding (a D1 database, or the self-hosted server's SQLite)
+    pub fn new(d1: crate::platform::D1Database) -> Self {
+        #[cfg(not(feature = "server"))]
+        let d1 = crate::storage::D1Storage::new(d1);
+        Self::with_storage(d1)
+    }
+}
+
+impl<S: Storage> Database<S> {
+    pub fn with_storage(storage: S) -> Self {
+        Self { storage }
     }
 
     fn generate_id() -> String {
@@ -97,20 +614,83 @@ impl Database {
         Utc::now().timestamp()
     }
 
-    #[allow(clippy::cast_precision_loss)] // D1 requires f64 for timestamps
-    fn timestamp_to_f64(timestamp: i64) -> JsValue {
-        (timestamp as f64).into()
+    async fn first<T: DeserializeOwned>(&self, statement: Statement) -> Result<Option<T>> {
+        Ok(self.all::<T>(statement).await?.into_iter().next())
+    }
+
+    async fn all<T: DeserializeOwned>(&self, statement: Statement) -> Result<Vec<T>> {
+        self.storage.execute(statement).await?.rows::<T>()
+    }
+
+    // Execute a write and return how many rows it changed
+    async fn run(&self, statement: Statement) -> Result<usize> {
+        Ok(self.storage.execute(statement).await?.changes())
+    }
+
+    // Names of all tables, used to work out which schema bookkeeping a database has
+    pub async fn table_names(&self) -> Result<Vec<String>> {
+        let stmt = Statement::new("SELECT name FROM sqlite_master WHERE type = 'table'");
+        let rows: Vec<serde_json::Value> = self.all::<serde_json::Value>(stmt).await?;
+        Ok(rows.iter()
+            .filter_map(|row| row.get("name").and_then(|name| name.as_str()).map(str::to_string))
+            .collect())
+    }
+
+    // Whether `table` has a column named `column`, for code that must also run on older schemas
+    pub async fn has_column(&self, table: &str, column: &str) -> Result<bool> {
+        let stmt = Statement::new(format!("PRAGMA table_info({table})"));
+        let rows: Vec<serde_json::Value> = self.all::<serde_json::Value>(stmt).await?;
+        Ok(rows.iter().any(|row| row.get("name").and_then(|name| name.as_str()) == Some(column)))
+    }
+
+    pub async fn max_schema_version(&self) -> Result<u32> {
+        let stmt = Statement::new("SELECT MAX(version) AS version FROM schema_version");
+        let result = self.first::<serde_json::Value>(stmt).await?;
+        
+        // D1 returns numbers as floats (same as COUNT in is_initialized)
+        Ok(result
+            .and_then(|value| value.get("version").and_then(|version| version.as_f64()))
+            .map(|version| version as u32)
+            .unwrap_or(0))
+    }
+
+    // Migration files wrangler has recorded as applied
+    pub async fn applied_wrangler_migrations(&self) -> Result<Vec<String>> {
+        let stmt = Statement::new("SELECT name FROM d1_migrations ORDER BY id");
+        let rows: Vec<serde_json::Value> = self.all::<serde_json::Value>(stmt).await?;
+        Ok(rows.iter()
+            .filter_map(|row| row.get("name").and_then(|name| name.as_str()).map(str::to_string))
+            .collect())
+    }
+
+    // Run one migration's statements in a single batch and record it where wrangler looks,
+    // so `wrangler d1 migrations apply` will not try to run it again
+    pub async fn apply_migration(&self, file_name: &str, statements: &[String]) -> Result<()> {
+        let mut batch = Vec::with_capacity(statements.len() + 2);
+        for sql in statements {
+            batch.push(Statement::new(sql.as_str()));
+        }
+        
+        // Same definition wrangler uses when it creates the table
+        batch.push(Statement::new(
+            "CREATE TABLE IF NOT EXISTS d1_migrations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE,
+                applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+             )"
+        ));
+        batch.push(Statement::new("INSERT OR IGNORE INTO d1_migrations (name) VALUES (?1)").bind(&[file_name.into()]));
+        
+        self.storage.batch(batch).await?;
+        Ok(())
     }
 
-    // Check if the database has been initialized (has any admin keys)
     pub async fn is_initialized(&self) -> Result<bool> {
-        let stmt = self.d1.prepare(
+        let stmt = Statement::new(
             "SELECT COUNT(*) as count FROM api_keys WHERE key_type = 'admin'"
         );
         
-        let result = stmt.bind(&[])?
-            .first::<serde_json::Value>(None)
-            .await?;
+        let result = self.first::<serde_json::Value>(stmt).await?;
             
         if let Some(value) = result {
             if let Some(count) = value.get("count") {
@@ -124,54 +704,113 @@ impl Database {
     }
 
     // Initialize the database with the first admin key (one-time operation)
-    pub async fn initialize_with_admin_key(&self, key_hash: String) -> Result<String> {
+    pub async fn initialize_with_admin_key(&self, key_hash: String, lookup_id: String) -> Result<String> {
         // Double-check that we're not already initialized
         if self.is_initialized().await? {
             return Err(worker::Error::RustError("Database already initialized".to_string()));
         }
         
-        let stmt = self.d1.prepare(
-            "INSERT INTO api_keys (id, key_hash, client_name, key_type, created_at, active) 
-             VALUES (?1, ?2, ?3, ?4, ?5, 1)"
+        let stmt = Statement::new(
+            "INSERT INTO api_keys (id, key_hash, lookup_id, client_name, key_type, created_at, active) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1)"
         );
         
         let id = Self::generate_id();
         let now = Self::current_timestamp();
         
-        stmt.bind(&[
+        self.run(stmt.bind(&[
             id.clone().into(),
             key_hash.into(),
+            lookup_id.into(),
             "Initial Admin Key".into(),
             "admin".into(),
-            Self::timestamp_to_f64(now),
-        ])?
-        .run()
-        .await?;
+            now.into(),
+        ])).await?;
         
         Ok(id)
     }
 
+    // Legacy lookup for rows stored before lookup ids and per-key salts (matched by hash)
+    // Expired keys are filtered here rather than in SQL: expires_at does not exist before migration 0016
     pub async fn validate_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>> {
-        let stmt = self.d1.prepare(
-            "SELECT id, key_hash, client_name, key_type, last_used, created_at, active 
-             FROM api_keys WHERE key_hash = ?1 AND active = 1"
+        let stmt = Statement::new(
+            "SELECT * FROM api_keys WHERE key_hash = ?1 AND active = 1"
         );
         
-        let result = stmt.bind(&[key_hash.into()])?.first::<ApiKeyRow>(None).await?;
-        let result = result.map(Into::into);
+        let result: Option<ApiKey> = self.first::<ApiKeyRow>(stmt.bind(&[key_hash.into()])).await?
+            .map(ApiKey::from)
+            .filter(|key| !key.is_expired(Self::current_timestamp()));
         
-        if result.is_some() {
-            let update_stmt = self.d1.prepare(
-                "UPDATE api_keys SET last_used = ?1 WHERE key_hash = ?2"
-            );
-            let now = Utc::now().timestamp();
-            update_stmt.bind(&[(now as f64).into(), key_hash.into()])?.run().await?;
+        if let Some(key) = &result {
+            self.touch_api_key(&key.id).await?;
         }
         
         Ok(result)
     }
 
-    pub async fn create_api_key(&self, key_hash: String, client_name: String, key_type: KeyType) -> Result<String> {
+    // Fetch an active, unexpired key by its public lookup id; the caller must still verify the hash
+    pub async fn find_api_key_by_lookup_id(&self, lookup_id: &str) -> Result<Option<ApiKey>> {
+        let stmt = Statement::new(
+            "SELECT * FROM api_keys WHERE lookup_id = ?1 AND active = 1"
+        );
+        
+        let result = match self.first::<ApiKeyRow>(stmt.bind(&[lookup_id.into()])).await {
+            Ok(result) => result,
+            // Below schema v4 there is no lookup_id column, so no key can match by lookup id yet
+            Err(_) if !self.has_column("api_keys", "lookup_id").await? => None,
+            Err(e) => return Err(e),
+        };
+        Ok(result.map(ApiKey::from).filter(|key| !key.is_expired(Self::current_timestamp())))
+    }
+
+    // Any key by id, revoked and expired ones included
+    pub async fn get_api_key(&self, id: &str) -> Result<Option<ApiKey>> {
+        let stmt = Statement::new(
+            "SELECT * FROM api_keys WHERE id = ?1"
+        ).bind(&[id.into()]);
+        
+        // Same as list_api_keys: a rotated key past its grace period shows as inactive
+        let results = self.storage.batch(vec![Self::retire_rotated_keys(Self::current_timestamp()), stmt]).await?;
+        let row = match results.last() {
+            Some(result) => result.rows::<ApiKeyRow>()?.into_iter().next(),
+            None => None,
+        };
+        Ok(row.map(Into::into))
+    }
+
+    // Replace a key's stored hash (transparent upgrade after a successful login)
+    pub async fn update_api_key_hash(&self, id: &str, key_hash: &str, lookup_id: &str) -> Result<()> {
+        let stmt = Statement::new(
+            "UPDATE api_keys SET key_hash = ?1, lookup_id = ?2 WHERE id = ?3"
+        );
+        
+        match self.run(stmt.bind(&[key_hash.into(), lookup_id.into(), id.into()])).await {
+            Ok(_) => Ok(()),
+            // Below schema v4 the key keeps its legacy hash, the only one it can be found by there
+            Err(_) if !self.has_column("api_keys", "lookup_id").await? => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Record that a key was just used
+    pub async fn touch_api_key(&self, id: &str) -> Result<()> {
+        let stmt = Statement::new(
+            "UPDATE api_keys SET last_used = ?1 WHERE id = ?2"
+        );
+        
+        self.run(stmt.bind(&[Self::current_timestamp().into(), id.into()])).await?;
+        Ok(())
+    }
+
+    pub async fn create_api_key(&self, key_hash: String, lookup_id: String, client_name: String, key_type: KeyType, options: &ApiKeyOptions) -> Result<String> {
+        let (id, stmt) = Self::insert_api_key(key_hash, lookup_id, client_name, key_type, options);
+        self.run(stmt).await?;
+        
+        Ok(id)
+    }
+
+    // The INSERT for a new active key and the id it assigns, shared with reinitialize_admin_keys
+    fn insert_api_key(key_hash: String, lookup_id: String, client_name: String, key_type: KeyType, options: &ApiKeyOptions) -> (String, Statement) {
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().timestamp();
         let key_type_str = match key_type {
@@ -179,252 +818,1426 @@ impl Database {
             KeyType::Client => "client",
         };
         
-        let stmt = self.d1.prepare(
-            "INSERT INTO api_keys (id, key_hash, client_name, key_type, created_at, active) 
-             VALUES (?1, ?2, ?3, ?4, ?5, 1)"
-        );
-        
-        stmt.bind(&[
+        let stmt = Statement::new(
+            "INSERT INTO api_keys (id, key_hash, lookup_id, client_name, key_type, created_at, active, rate_limit_per_minute, scopes, expires_at) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1, ?7, ?8, ?9)"
+        ).bind(&[
             id.clone().into(),
             key_hash.into(),
+            lookup_id.into(),
             client_name.into(),
             key_type_str.into(),
-            (now as f64).into(),
-        ])?
-        .run()
-        .await?;
+            now.into(),
+            options.rate_limit_per_minute.map(i64::from).into(),
+            options.scopes.as_deref().map(format_scopes).into(),
+            options.expires_at.into(),
+        ]);
         
-        Ok(id)
+        (id, stmt)
     }
 
-    // Reinitialize: deactivate ALL admin keys and create a new one (emergency rotation)
-    pub async fn reinitialize_admin_keys(&self, new_key_hash: String) -> Result<String> {
-        // Deactivate all existing admin keys
-        let deactivate_stmt = self.d1.prepare(
-            "UPDATE api_keys SET active = 0 WHERE key_type = 'admin'"
-        );
-        deactivate_stmt.bind(&[])?.run().await?;
+    // Reinitialize: deactivate ALL admin keys and create a new one (emergency rotation).
+    // The new key takes over the todos, tags and sync tombstones of the keys deactivated here,
+    // all in one batch so a failure leaves the old keys in place. Keys revoked earlier keep
+    // what they own.
+    pub async fn reinitialize_admin_keys(&self, new_key_hash: String, lookup_id: String) -> Result<String> {
+        let (id, insert) = Self::insert_api_key(new_key_hash, lookup_id, "Reinitialized Admin Key".to_string(), KeyType::Admin, &ApiKeyOptions::default());
+        
+        let statements = vec![
+            insert,
+            // Hand the todos to the new key so they stay reachable
+            Statement::new(format!(
+                "UPDATE todos SET owner_key_id = ?1 WHERE owner_key_id IN ({REINITIALIZED_OWNERS})"
+            )).bind(&[id.as_str().into()]),
+            // The old keys may share tag names; merge each name into the tag with the lowest id
+            // so the move below cannot break UNIQUE(owner_key_id, name)
+            Statement::new(format!(
+                "INSERT OR IGNORE INTO todo_tags (todo_id, tag_id)
+                 SELECT todo_tags.todo_id, (
+                     SELECT MIN(keep.id) FROM tags keep
+                     WHERE keep.name = tags.name AND keep.owner_key_id IN ({REINITIALIZED_OWNERS})
+                 )
+                 FROM todo_tags JOIN tags ON tags.id = todo_tags.tag_id
+                 WHERE tags.owner_key_id IN ({REINITIALIZED_OWNERS})"
+            )).bind(&[id.as_str().into()]),
+            // Deleting the merged duplicates drops their links through ON DELETE CASCADE
+            Statement::new(format!(
+                "DELETE FROM tags
+                 WHERE owner_key_id IN ({REINITIALIZED_OWNERS}) AND id != (
+                     SELECT MIN(keep.id) FROM tags keep
+                     WHERE keep.name = tags.name AND keep.owner_key_id IN ({REINITIALIZED_OWNERS})
+                 )"
+            )).bind(&[id.as_str().into()]),
+            Statement::new(format!(
+                "UPDATE tags SET owner_key_id = ?1 WHERE owner_key_id IN ({REINITIALIZED_OWNERS})"
+            )).bind(&[id.as_str().into()]),
+            Statement::new(format!(
+                "UPDATE todo_tombstones SET owner_key_id = ?1 WHERE owner_key_id IN ({REINITIALIZED_OWNERS})"
+            )).bind(&[id.as_str().into()]),
+            // Deactivate the old admin keys last, as the statements above select owners through them
+            Statement::new(
+                "UPDATE api_keys SET active = 0, revoked_at = ?2 WHERE key_type = 'admin' AND active = 1 AND id != ?1"
+            ).bind(&[id.as_str().into(), Self::current_timestamp().into()]),
+        ];
+        self.storage.batch(statements).await?;
         
-        // Create new admin key
-        let id = self.create_api_key(new_key_hash, "Reinitialized Admin Key".to_string(), KeyType::Admin).await?;
         Ok(id)
     }
 
-    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
-        let stmt = self.d1.prepare(
-            "SELECT id, key_hash, client_name, key_type, last_used, created_at, active 
-             FROM api_keys ORDER BY created_at DESC"
+    // Record an emergency reinitialization attempt for throttling and later review
+    pub async fn record_recovery_attempt(&self, client_ip: &str, success: bool) -> Result<()> {
+        let stmt = Statement::new(
+            "INSERT INTO recovery_attempts (client_ip, success, attempted_at) VALUES (?1, ?2, ?3)"
         );
         
-        let results = stmt.bind(&[])?.all().await?;
-        
-        let rows: Vec<ApiKeyRow> = results.results::<ApiKeyRow>()?;
-        let keys: Vec<ApiKey> = rows.into_iter().map(Into::into).collect();
+        self.run(stmt.bind(&[
+            client_ip.into(),
+            i32::from(success).into(),
+            Self::current_timestamp().into(),
+        ])).await?;
         
-        Ok(keys)
+        Ok(())
     }
 
-    pub async fn revoke_api_key(&self, id: &str) -> Result<()> {
-        let stmt = self.d1.prepare(
-            "UPDATE api_keys SET active = 0 WHERE id = ?1"
+    // Count failed reinitialization attempts from a client since the given timestamp
+    pub async fn count_failed_recovery_attempts(&self, client_ip: &str, since: i64) -> Result<u32> {
+        let stmt = Statement::new(
+            "SELECT COUNT(*) as count FROM recovery_attempts 
+             WHERE client_ip = ?1 AND success = 0 AND attempted_at >= ?2"
         );
         
-        stmt.bind(&[id.into()])?.run().await?;
-        Ok(())
-    }
-
-    pub async fn create_todo(&self, req: CreateTodoRequest) -> Result<Todo> {
-        let id = Uuid::new_v4().to_string();
-        let now = Utc::now().timestamp();
-        let priority = req.priority.unwrap_or(2);
+        let result = self.first::<serde_json::Value>(stmt.bind(&[client_ip.into(), since.into()])).await?;
         
-        let stmt = self.d1.prepare(
-            "INSERT INTO todos (id, title, description, completed, priority, due_date, created_at, updated_at) 
-             VALUES (?1, ?2, ?3, 0, ?4, ?5, ?6, ?7)"
-        );
+        // D1 returns COUNT(*) as a float, same as is_initialized
+        let count = result
+            .and_then(|value| value.get("count").and_then(serde_json::Value::as_f64))
+            .unwrap_or(0.0);
         
-        // WORKAROUND: D1 NULL handling issue  
-        // Issue: https://github.com/cloudflare/workers-rs/issues/678
-        // D1 binding doesn't support Option<T> directly - None becomes "undefined"
-        // which causes "Type 'undefined' not supported" errors.
-        // Solution: Manually convert None to JsValue::NULL for database compatibility.
-        stmt.bind(&[
-            id.clone().into(),
-            req.title.clone().into(),
-            match req.description.clone() {
-                Some(desc) => desc.into(),
-                None => JsValue::NULL,  // Consistent NULL handling
-            },
-            priority.into(),
-            match req.due_date {
-                Some(date) => (date as f64).into(),
-                None => JsValue::NULL,  // Required for D1 NULL handling
-            },
-            (now as f64).into(),
-            (now as f64).into(),
-        ])?
-        .run()
-        .await?;
-        
-        Ok(Todo {
-            id,
-            title: req.title,
-            description: req.description,
-            completed: false,
-            priority,
-            due_date: req.due_date,
-            created_at: now,
-            updated_at: now,
-        })
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Ok(count as u32)
     }
 
-    pub async fn list_todos(&self, completed_filter: Option<bool>) -> Result<Vec<Todo>> {
-        let query = match completed_filter {
-            Some(completed) => {
-                let stmt = self.d1.prepare(
-                    "SELECT * FROM todos WHERE completed = ?1 ORDER BY priority DESC, created_at DESC"
-                );
-                stmt.bind(&[i32::from(completed).into()])?
-            },
-            None => {
-                let stmt = self.d1.prepare(
-                    "SELECT * FROM todos ORDER BY priority DESC, created_at DESC"
-                );
-                stmt.bind(&[])?
-            }
+    pub async fn list_api_keys(&self, page: &PageRequest<ApiKeyCursor>) -> Result<Page<ApiKey>> {
+        let stmt = match &page.after {
+            Some(cursor) => Statement::new(
+                "SELECT id, key_hash, client_name, key_type, last_used, created_at, active, rate_limit_per_minute, scopes, expires_at, rotated_from, replaced_by, revoked_at 
+                 FROM api_keys WHERE (created_at, id) < (?1, ?2)
+                 ORDER BY created_at DESC, id DESC LIMIT ?3"
+            ).bind(&[
+                cursor.created_at.into(),
+                cursor.id.as_str().into(),
+                page.fetch_limit().into(),
+            ]),
+            None => Statement::new(
+                "SELECT id, key_hash, client_name, key_type, last_used, created_at, active, rate_limit_per_minute, scopes, expires_at, rotated_from, replaced_by, revoked_at 
+                 FROM api_keys ORDER BY created_at DESC, id DESC LIMIT ?1"
+            ).bind(&[page.fetch_limit().into()]),
         };
         
-        let results = query.all().await?;
-        let rows: Vec<TodoRow> = results.results::<TodoRow>()?;
-        let todos: Vec<Todo> = rows.into_iter().map(Into::into).collect();
+        // Deactivate rotated keys whose grace period is over first, so the list shows them as such
+        let results = self.storage.batch(vec![Self::retire_rotated_keys(Self::current_timestamp()), stmt]).await?;
+        let rows: Vec<ApiKeyRow> = match results.last() {
+            Some(result) => result.rows::<ApiKeyRow>()?,
+            None => Vec::new(),
+        };
+        let keys: Vec<ApiKey> = rows.into_iter().map(Into::into).collect();
         
-        Ok(todos)
+        Ok(Page::from_rows(keys, page.limit, ApiKeyCursor::after))
     }
 
-    pub async fn get_todo(&self, id: &str) -> Result<Option<Todo>> {
-        let stmt = self.d1.prepare("SELECT * FROM todos WHERE id = ?1");
-        let result = stmt.bind(&[id.into()])?.first::<TodoRow>(None).await?;
-        Ok(result.map(Into::into))
+    // Keys replaced by a rotation stop authenticating at their expires_at; this flips them to inactive
+    fn retire_rotated_keys(now: i64) -> Statement {
+        Statement::new(
+            "UPDATE api_keys SET active = 0, revoked_at = expires_at
+             WHERE replaced_by IS NOT NULL AND active = 1 AND expires_at <= ?1"
+        ).bind(&[now.into()])
     }
 
-    pub async fn update_todo(&self, id: &str, req: UpdateTodoRequest) -> Result<Option<Todo>> {
-        let existing = self.get_todo(id).await?;
+    // Issue a successor for an active, unexpired and not yet rotated key, in one batch
+    // The successor copies the old key's name, type, scopes, budget and expiry and works on the same
+    // todos; the old key now expires at `previous_expires_at` (or sooner, if it already did).
+    // Returns the successor's id and the old key's new expiry, or None if the key cannot be rotated.
+    pub async fn rotate_api_key(&self, id: &str, key_hash: String, lookup_id: String, previous_expires_at: i64) -> Result<Option<(String, i64)>> {
+        let successor_id = Uuid::new_v4().to_string();
+        let now = Self::current_timestamp();
         
-        if let Some(mut todo) = existing {
-            if let Some(title) = req.title {
-                todo.title = title;
-            }
-            if let Some(desc) = req.description {
-                todo.description = Some(desc);
-            }
-            if let Some(completed) = req.completed {
-                todo.completed = completed;
-            }
-            if let Some(priority) = req.priority {
-                todo.priority = priority;
-            }
-            if let Some(due_date) = req.due_date {
-                todo.due_date = Some(due_date);
-            }
-            
-            todo.updated_at = Utc::now().timestamp();
-            
-            let stmt = self.d1.prepare(
-                "UPDATE todos SET title = ?1, description = ?2, completed = ?3, 
-                 priority = ?4, due_date = ?5, updated_at = ?6 WHERE id = ?7"
-            );
-            
-            stmt.bind(&[
-                todo.title.clone().into(),
-                match todo.description.clone() {
-                    Some(desc) => desc.into(),
-                    None => JsValue::NULL,  // Consistent NULL handling
-                },
-                i32::from(todo.completed).into(),
-                todo.priority.into(),
-                match todo.due_date {
-                    Some(date) => (date as f64).into(),
-                    None => JsValue::NULL,  // D1 NULL handling workaround
-                },
-                (todo.updated_at as f64).into(),
-                id.into(),
-            ])?
-            .run()
-            .await?;
-            
-            Ok(Some(todo))
-        } else {
-            Ok(None)
-        }
-    }
-
-    pub async fn toggle_todo(&self, id: &str) -> Result<Option<Todo>> {
-        if let Some(mut todo) = self.get_todo(id).await? {
-            todo.completed = !todo.completed;
-            todo.updated_at = Utc::now().timestamp();
-            
-            let stmt = self.d1.prepare(
-                "UPDATE todos SET completed = ?1, updated_at = ?2 WHERE id = ?3"
-            );
-            
-            stmt.bind(&[
-                i32::from(todo.completed).into(),
-                (todo.updated_at as f64).into(),
-                id.into(),
-            ])?
-            .run()
-            .await?;
-            
-            Ok(Some(todo))
-        } else {
-            Ok(None)
-        }
+        let insert = Statement::new(
+            "INSERT INTO api_keys (id, key_hash, lookup_id, client_name, key_type, created_at, active, rate_limit_per_minute, scopes, expires_at, owner_key_id, rotated_from) 
+             SELECT ?1, ?2, ?3, client_name, key_type, ?4, 1, rate_limit_per_minute, scopes, expires_at, COALESCE(owner_key_id, id), id
+             FROM api_keys
+             WHERE id = ?5 AND active = 1 AND replaced_by IS NULL AND (expires_at IS NULL OR expires_at > ?4)"
+        ).bind(&[
+            successor_id.as_str().into(),
+            key_hash.into(),
+            lookup_id.into(),
+            now.into(),
+            id.into(),
+        ]);
+        // Only links the old key if the insert above created its successor
+        let link = Statement::new(
+            "UPDATE api_keys SET replaced_by = ?1, expires_at = MIN(COALESCE(expires_at, ?2), ?2)
+             WHERE id = ?3 AND EXISTS (SELECT 1 FROM api_keys WHERE id = ?1)
+             RETURNING expires_at"
+        ).bind(&[
+            successor_id.as_str().into(),
+            previous_expires_at.into(),
+            id.into(),
+        ]);
+        
+        let results = self.storage.batch(vec![insert, link, Self::retire_rotated_keys(now)]).await?;
+        let linked = match results.get(1) {
+            Some(result) => result.rows::<ExpiresAtRow>()?.into_iter().next(),
+            None => None,
+        };
+        Ok(linked.map(|row| (successor_id, row.expires_at)))
     }
 
-    pub async fn delete_todo(&self, id: &str) -> Result<bool> {
-        let stmt = self.d1.prepare("DELETE FROM todos WHERE id = ?1");
-        let result = stmt.bind(&[id.into()])?.run().await?;
-        Ok(result.meta()?.is_some())
+    // None removes the expiry. Shortening the expiry of an active admin key needs another
+    // active admin key that can manage keys, as for revoke_api_key.
+    // Returns false when no key has this id or the change was refused for that reason
+    pub async fn set_api_key_expiry(&self, id: &str, expires_at: Option<i64>) -> Result<bool> {
+        let stmt = Statement::new(format!(
+            "UPDATE api_keys SET expires_at = ?3
+             WHERE id = ?1 AND (key_type != 'admin' OR active = 0 OR ?3 IS NULL
+                 OR (expires_at IS NOT NULL AND ?3 >= expires_at) OR {OTHER_KEY_MANAGER_EXISTS})"
+        ));
+        
+        Ok(self.run(stmt.bind(&[id.into(), Self::current_timestamp().into(), expires_at.into()])).await? > 0)
     }
 
-    // TODO: Implement proper full-text search indexing
-    // TODO: Add search relevance scoring
-    // TODO: Consider using D1's full-text search features when available
-    pub async fn search_todos(&self, query: &str) -> Result<Vec<Todo>> {
-        let stmt = self.d1.prepare(
-            "SELECT * FROM todos WHERE title LIKE ?1 OR description LIKE ?1 
-             ORDER BY priority DESC, created_at DESC"
+    // Rename a key; returns false when no key has this id
+    pub async fn rename_api_key(&self, id: &str, client_name: &str) -> Result<bool> {
+        let stmt = Statement::new(
+            "UPDATE api_keys SET client_name = ?1 WHERE id = ?2"
         );
         
-        let search_pattern = format!("%{query}%");
-        let results = stmt.bind(&[search_pattern.into()])?.all().await?;
+        Ok(self.run(stmt.bind(&[client_name.into(), id.into()])).await? > 0)
+    }
+
+    // Deactivate an active key, unless it is the last active admin key that can manage keys
+    // Returns false when the key was not deactivated for that reason or is already inactive
+    pub async fn revoke_api_key(&self, id: &str) -> Result<bool> {
+        let stmt = Statement::new(format!(
+            "UPDATE api_keys SET active = 0, revoked_at = ?2
+             WHERE id = ?1 AND active = 1 AND (key_type != 'admin' OR {OTHER_KEY_MANAGER_EXISTS})"
+        ));
         
-        let rows: Vec<TodoRow> = results.results::<TodoRow>()?;
-        let todos: Vec<Todo> = rows.into_iter().map(Into::into).collect();
+        Ok(self.run(stmt.bind(&[id.into(), Self::current_timestamp().into()])).await? > 0)
+    }
+
+    // Reactivate a revoked key; keys replaced by a rotation stay retired
+    pub async fn reactivate_api_key(&self, id: &str) -> Result<bool> {
+        let stmt = Statement::new(
+            "UPDATE api_keys SET active = 1, revoked_at = NULL WHERE id = ?1 AND replaced_by IS NULL"
+        );
+        
+        Ok(self.run(stmt.bind(&[id.into()])).await? > 0)
+    }
+
+    // Hard-delete keys revoked before `revoked_before`, with their tags, idempotency records,
+    // rate limit bucket and sync tombstones. Keys that still own todos, or whose todos other keys
+    // work on after a rotation, are retained. Returns the purged and the retained ids.
+    pub async fn purge_api_keys(&self, revoked_before: i64) -> Result<(Vec<String>, Vec<String>)> {
+        let candidates = Statement::new(
+            "SELECT id,
+                    EXISTS (SELECT 1 FROM todos WHERE todos.owner_key_id = api_keys.id) AS owns_todos
+             FROM api_keys WHERE active = 0 AND revoked_at < ?1"
+        ).bind(&[revoked_before.into()]);
+        let owners = Statement::new(
+            "SELECT DISTINCT owner_key_id AS id FROM api_keys WHERE owner_key_id IS NOT NULL"
+        );
+        
+        let results = self.storage.batch(vec![candidates, owners]).await?;
+        let (candidates, owners) = match results.as_slice() {
+            [candidates, owners] => (candidates.rows::<PurgeCandidateRow>()?, owners.rows::<IdRow>()?),
+            _ => return Ok((Vec::new(), Vec::new())),
+        };
+        
+        // A key referenced as owner by any other key keeps its row, so those keys never point at nothing
+        let owners: HashSet<String> = owners.into_iter().map(|row| row.id).collect();
+        let (purged, retained): (Vec<PurgeCandidateRow>, Vec<PurgeCandidateRow>) = candidates.into_iter()
+            .partition(|row| row.owns_todos == 0 && !owners.contains(&row.id));
+        let purged: Vec<String> = purged.into_iter().map(|row| row.id).collect();
+        let retained: Vec<String> = retained.into_iter().map(|row| row.id).collect();
+        if purged.is_empty() {
+            return Ok((purged, retained));
+        }
+        
+        let ids_json = serde_json::to_string(&purged)?;
+        let buckets_json = serde_json::to_string(&purged.iter().map(|id| format!("key:{}", id)).collect::<Vec<_>>())?;
+        let ids = || -> SqlValue { ids_json.as_str().into() };
+        let statements = vec![
+            Statement::new("DELETE FROM tags WHERE owner_key_id IN (SELECT value FROM json_each(?1))").bind(&[ids()]),
+            Statement::new("DELETE FROM idempotency_keys WHERE api_key_id IN (SELECT value FROM json_each(?1))").bind(&[ids()]),
+            Statement::new("DELETE FROM todo_tombstones WHERE owner_key_id IN (SELECT value FROM json_each(?1))").bind(&[ids()]),
+            Statement::new("DELETE FROM rate_limit_buckets WHERE bucket IN (SELECT value FROM json_each(?1))").bind(&[buckets_json.as_str().into()]),
+            // active = 0 again, in case a key was reactivated since the candidates were read
+            Statement::new("DELETE FROM api_keys WHERE id IN (SELECT value FROM json_each(?1)) AND active = 0").bind(&[ids()]),
+        ];
+        self.storage.batch(statements).await?;
+        
+        Ok((purged, retained))
+    }
+
+    // Take one token from a rate limit bucket, refilling it first at `per_minute` tokens per minute
+    // A key's own rate_limit_per_minute (looked up via key_id) replaces `per_minute`. Refill and
+    // spend happen in one upsert, so concurrent requests can never spend the same token twice.
+    pub async fn take_rate_limit_token(&self, bucket: &str, key_id: Option<&str>, per_minute: u32, now: f64) -> Result<Option<RateLimitBucket>> {
+        // Idle buckets are full again after a minute, so dropping them changes nothing
+        let cleanup = Statement::new("DELETE FROM rate_limit_buckets WHERE updated_at < ?1")
+            .bind(&[(now - 60.0).into()]);
+        
+        // WHERE true disambiguates INSERT ... SELECT from the upsert clause for SQLite's parser
+        let take = Statement::new(
+            "INSERT INTO rate_limit_buckets (bucket, capacity, tokens, allowed, updated_at)
+             SELECT ?1, capacity, capacity - 1, 1, ?4
+             FROM (SELECT COALESCE((SELECT rate_limit_per_minute FROM api_keys WHERE id = ?2), ?3) AS capacity)
+             WHERE true
+             ON CONFLICT(bucket) DO UPDATE SET
+                 capacity = excluded.capacity,
+                 allowed = (MIN(excluded.capacity, tokens + MAX(0, excluded.updated_at - updated_at) * excluded.capacity / 60.0) >= 1),
+                 tokens = MIN(excluded.capacity, tokens + MAX(0, excluded.updated_at - updated_at) * excluded.capacity / 60.0)
+                     - (MIN(excluded.capacity, tokens + MAX(0, excluded.updated_at - updated_at) * excluded.capacity / 60.0) >= 1),
+                 updated_at = excluded.updated_at
+             RETURNING capacity, tokens, allowed"
+        ).bind(&[
+            bucket.into(),
+            key_id.into(),
+            i64::from(per_minute).into(),
+            now.into(),
+        ]);
+        
+        let results = self.storage.batch(vec![cleanup, take]).await?;
+        match results.last() {
+            Some(result) => Ok(result.rows::<RateLimitBucket>()?.into_iter().next()),
+            None => Ok(None),
+        }
+    }
+
+    // Append audit events in one batch, dropping those older than `expires_before` on the way
+    pub async fn record_audit_events(&self, events: &[AuditEvent], expires_before: i64) -> Result<()> {
+        let mut statements = vec![
+            Statement::new("DELETE FROM audit_events WHERE created_at < ?1").bind(&[expires_before.into()]),
+        ];
+        
+        for event in events {
+            let changes = event.changes.as_ref().map(serde_json::to_string).transpose()?;
+            statements.push(Statement::new(
+                "INSERT INTO audit_events (event_type, actor_key_id, target_id, client_ip, method, path, request_id, changes, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"
+            ).bind(&[
+                event.event_type.as_str().into(),
+                event.actor_key_id.as_deref().into(),
+                event.target_id.as_deref().into(),
+                event.client_ip.as_deref().into(),
+                event.method.as_deref().into(),
+                event.path.as_deref().into(),
+                event.request_id.as_deref().into(),
+                changes.into(),
+                event.created_at.into(),
+            ]));
+        }
+        
+        self.storage.batch(statements).await?;
+        Ok(())
+    }
+
+    // Newest events first
+    pub async fn list_audit_events(&self, filter: &AuditFilter, page: &PageRequest<AuditCursor>) -> Result<Page<AuditEvent>> {
+        let stmt = Statement::new(
+            "SELECT * FROM audit_events
+             WHERE (?1 IS NULL OR event_type = ?1) AND (?2 IS NULL OR actor_key_id = ?2)
+             AND (?3 IS NULL OR target_id = ?3) AND (?4 IS NULL OR client_ip = ?4)
+             AND (?5 IS NULL OR created_at >= ?5) AND (?6 IS NULL OR created_at < ?6)
+             AND (?7 IS NULL OR id < ?7)
+             ORDER BY id DESC LIMIT ?8"
+        ).bind(&[
+            filter.event_type.as_deref().into(),
+            filter.actor_key_id.as_deref().into(),
+            filter.target_id.as_deref().into(),
+            filter.client_ip.as_deref().into(),
+            filter.created_after.into(),
+            filter.created_before.into(),
+            page.after.as_ref().map(|cursor| cursor.id).into(),
+            page.fetch_limit().into(),
+        ]);
+        
+        let rows: Vec<AuditEventRow> = self.all::<AuditEventRow>(stmt).await?;
+        let events: Vec<AuditEvent> = rows.into_iter().map(Into::into).collect();
+        
+        Ok(Page::from_rows(events, page.limit, AuditCursor::after))
+    }
+
+    // Claim an idempotency key for a new request; false means a live record already exists
+    // Expired records, and in-flight ones abandoned for longer than stale_after, are taken over
+    pub async fn claim_idempotency_key(&self, api_key_id: &str, key: &str, request_hash: &str, expires_before: i64, stale_before: i64) -> Result<bool> {
+        let now = Self::current_timestamp();
+        
+        // Opportunistic cleanup keeps the table bounded without a scheduled job
+        self.run(Statement::new("DELETE FROM idempotency_keys WHERE created_at < ?1").bind(&[expires_before.into()])).await?;
+        
+        let stmt = Statement::new(
+            "INSERT INTO idempotency_keys (api_key_id, idempotency_key, request_hash, created_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (api_key_id, idempotency_key) DO UPDATE SET
+                request_hash = excluded.request_hash, status_code = NULL,
+                response_headers = NULL, response_body = NULL, created_at = excluded.created_at
+             WHERE idempotency_keys.status_code IS NULL AND idempotency_keys.created_at < ?5"
+        );
+        let changes = self.run(stmt.bind(&[
+            api_key_id.into(),
+            key.into(),
+            request_hash.into(),
+            now.into(),
+            stale_before.into(),
+        ])).await?;
+        
+        Ok(changes > 0)
+    }
+
+    pub async fn find_idempotency_record(&self, api_key_id: &str, key: &str) -> Result<Option<IdempotencyRecord>> {
+        let stmt = Statement::new(
+            "SELECT request_hash, status_code, response_headers, response_body FROM idempotency_keys
+             WHERE api_key_id = ?1 AND idempotency_key = ?2"
+        );
+        self.first::<IdempotencyRecord>(stmt.bind(&[api_key_id.into(), key.into()])).await
+    }
+
+    pub async fn complete_idempotency_key(&self, api_key_id: &str, key: &str, status_code: u16, headers: &str, body: &str) -> Result<()> {
+        let stmt = Statement::new(
+            "UPDATE idempotency_keys SET status_code = ?1, response_headers = ?2, response_body = ?3
+             WHERE api_key_id = ?4 AND idempotency_key = ?5"
+        );
+        self.run(stmt.bind(&[status_code.into(), headers.into(), body.into(), api_key_id.into(), key.into()])).await?;
+        Ok(())
+    }
+
+    // Forget a claim whose request failed so the client can retry with the same key
+    pub async fn release_idempotency_key(&self, api_key_id: &str, key: &str) -> Result<()> {
+        let stmt = Statement::new(
+            "DELETE FROM idempotency_keys WHERE api_key_id = ?1 AND idempotency_key = ?2 AND status_code IS NULL"
+        );
+        self.run(stmt.bind(&[api_key_id.into(), key.into()])).await?;
+        Ok(())
+    }
+
+    pub async fn create_todo(&self, owner_key_id: &str, req: CreateTodoRequest, tags: &[String]) -> Result<TodoResponse> {
+        let (todo, statements) = self.create_todo_statements(owner_key_id, req, tags, Self::current_timestamp())?;
+        
+        // Insert the todo and its tags in one batch so a failure leaves nothing behind
+        self.storage.batch(statements).await?;
+        Ok(todo)
+    }
+
+    // Statements inserting a new todo with its tags, plus the todo they will produce
+    fn create_todo_statements(&self, owner_key_id: &str, req: CreateTodoRequest, tags: &[String], now: i64) -> Result<(TodoResponse, Vec<Statement>)> {
+        let id = Uuid::new_v4().to_string();
+        let priority = req.priority.unwrap_or(2);
+        
+        let stmt = Statement::new(
+            "INSERT INTO todos (id, title, description, completed, priority, due_date, created_at, updated_at, owner_key_id) 
+             VALUES (?1, ?2, ?3, 0, ?4, ?5, ?6, ?7, ?8)"
+        );
+        
+        // None binds as NULL (see the D1 workaround in storage.rs)
+        let insert = stmt.bind(&[
+            id.clone().into(),
+            req.title.clone().into(),
+            req.description.clone().into(),
+            priority.into(),
+            req.due_date.into(),
+            now.into(),
+            now.into(),
+            owner_key_id.into(),
+        ]);
+        
+        let mut statements = vec![insert];
+        statements.extend(self.set_tags_statements(&id, tags, now, &TodoScope::AllOwners, &VersionMatch::Any)?);
+        
+        let todo = TodoResponse {
+            todo: Todo {
+                id,
+                title: req.title,
+                description: req.description,
+                completed: false,
+                priority,
+                due_date: req.due_date,
+                created_at: now,
+                updated_at: now,
+            },
+            version: 1,
+            tags: tags.to_vec(),
+        };
+        Ok((todo, statements))
+    }
+
+    pub async fn list_todos(&self, scope: &TodoScope, filter: &TodoFilter, sort: &TodoSort, page: &PageRequest<TodoCursor>) -> Result<Page<TodoResponse>> {
+        let mut conditions = vec!["(?1 IS NULL OR owner_key_id = ?1)".to_string()];
+        let mut params = vec![scope.owner_param()];
+        
+        if let Some(completed) = filter.completed {
+            let p = bind_param(&mut params, i32::from(completed).into());
+            conditions.push(format!("completed = {p}"));
+        }
+        
+        if let Some(priority) = filter.priority {
+            let p = bind_param(&mut params, priority.into());
+            conditions.push(format!("priority = {p}"));
+        }
+        if let Some(min) = filter.priority_min {
+            let p = bind_param(&mut params, min.into());
+            conditions.push(format!("priority >= {p}"));
+        }
+        if let Some(max) = filter.priority_max {
+            let p = bind_param(&mut params, max.into());
+            conditions.push(format!("priority <= {p}"));
+        }
+        
+        // Timestamp ranges: inclusive lower bound, exclusive upper bound
+        let ranges = [
+            ("due_date", filter.due_after, filter.due_before),
+            ("created_at", filter.created_after, filter.created_before),
+            ("updated_at", filter.updated_after, filter.updated_before),
+        ];
+        for (column, after, before) in ranges {
+            if let Some(after) = after {
+                let p = bind_param(&mut params, after.into());
+                conditions.push(format!("{column} >= {p}"));
+            }
+            if let Some(before) = before {
+                let p = bind_param(&mut params, before.into());
+                conditions.push(format!("{column} < {p}"));
+            }
+        }
+        
+        let now = Self::current_timestamp();
+        if filter.overdue {
+            let p = bind_param(&mut params, now.into());
+            conditions.push(format!("completed = 0 AND due_date IS NOT NULL AND due_date < {p}"));
+        }
+        if filter.due_today {
+            // Start of the caller's local day, expressed in UTC
+            let offset = filter.tz_offset_minutes * 60;
+            let local_now = now + offset;
+            let day_start = local_now - local_now.rem_euclid(86_400) - offset;
+            let start = bind_param(&mut params, day_start.into());
+            let end = bind_param(&mut params, (day_start + 86_400).into());
+            conditions.push(format!("due_date >= {start} AND due_date < {end}"));
+        }
+        
+        match filter.has_description {
+            Some(true) => conditions.push("(description IS NOT NULL AND description != '')".to_string()),
+            Some(false) => conditions.push("(description IS NULL OR description = '')".to_string()),
+            None => {},
+        }
+        
+        if !filter.tags.is_empty() {
+            let tags_param = bind_param(&mut params, serde_json::to_string(&filter.tags)?.into());
+            match filter.tag_match {
+                TagMatch::Any => conditions.push(format!(
+                    "id IN (SELECT tt.todo_id FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id
+                     WHERE t.name IN (SELECT value FROM json_each({tags_param})))"
+                )),
+                TagMatch::All => {
+                    let count_param = bind_param(&mut params, filter.tags.len().into());
+                    conditions.push(format!(
+                        "(SELECT COUNT(DISTINCT t.name) FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id
+                          WHERE tt.todo_id = todos.id AND t.name IN (SELECT value FROM json_each({tags_param}))) = {count_param}"
+                    ));
+                },
+            }
+        }
+        
+        self.query_todos(conditions, params, sort, page).await
+    }
+
+    // Run a filtered todo query with keyset pagination over the requested sort
+    async fn query_todos(&self, mut conditions: Vec<String>, mut params: Vec<SqlValue>, sort: &TodoSort, page: &PageRequest<TodoCursor>) -> Result<Page<TodoResponse>> {
+        let mut columns = sort.columns();
+        columns.push(("id".to_string(), sort.direction));
+        
+        // Rows after the cursor: (a > x) OR (a = x AND b > y) OR (a = x AND b = y AND id > z)
+        if let Some(cursor) = &page.after {
+            let mut values: Vec<SqlValue> = cursor.keys.iter().map(json_to_param).collect();
+            values.push(cursor.id.as_str().into());
+            let placeholders: Vec<String> = values.into_iter()
+                .map(|value| bind_param(&mut params, value))
+                .collect();
+            
+            let alternatives: Vec<String> = (0..columns.len())
+                .map(|i| {
+                    let mut terms: Vec<String> = (0..i)
+                        .map(|j| format!("{} = {}", columns[j].0, placeholders[j]))
+                        .collect();
+                    terms.push(format!("{} {} {}", columns[i].0, columns[i].1.after_operator(), placeholders[i]));
+                    format!("({})", terms.join(" AND "))
+                })
+                .collect();
+            conditions.push(format!("({})", alternatives.join(" OR ")));
+        }
+        
+        let order_by: Vec<String> = columns.iter()
+            .map(|(expression, direction)| format!("{} {}", expression, direction.sql()))
+            .collect();
+        let limit = bind_param(&mut params, page.fetch_limit().into());
+        let sql = format!(
+            "SELECT * FROM todos WHERE {} ORDER BY {} LIMIT {}",
+            conditions.join(" AND "),
+            order_by.join(", "),
+            limit
+        );
+        
+        let rows: Vec<TodoRow> = self.all::<TodoRow>(Statement::new(sql).bind(&params)).await?;
+        let todos: Vec<TodoResponse> = rows.into_iter().map(Into::into).collect();
+        
+        let todos = self.attach_tags(todos).await?;
+        let sort = *sort;
+        Ok(Page::from_rows(todos, page.limit, |todo| TodoCursor::after(sort, todo)))
+    }
+
+    // Raw row lookup (keeps owner_key_id, which Todo does not expose)
+    async fn get_todo_row(&self, scope: &TodoScope, id: &str) -> Result<Option<TodoRow>> {
+        let stmt = Statement::new(
+            "SELECT * FROM todos WHERE id = ?1 AND (?2 IS NULL OR owner_key_id = ?2)"
+        );
+        self.first::<TodoRow>(stmt.bind(&[id.into(), scope.owner_param()])).await
+    }
+
+    pub async fn get_todo(&self, scope: &TodoScope, id: &str) -> Result<Option<TodoResponse>> {
+        match self.get_todo_row(scope, id).await? {
+            Some(row) => Ok(Some(self.attach_tags_one(row.into()).await?)),
+            None => Ok(None),
+        }
+    }
+
+    // Single conditional UPDATE: fields left out of the request keep their stored values
+    pub async fn update_todo(&self, scope: &TodoScope, id: &str, req: UpdateTodoRequest, tags: Option<&[String]>, expected: &VersionMatch) -> Result<TodoWrite<TodoResponse>> {
+        let now = Self::current_timestamp();
+        let update = self.update_todo_statement(scope, id, req, now, expected)?;
+        self.run_todo_write(scope, id, update, tags, now, expected).await
+    }
+
+    fn update_todo_statement(&self, scope: &TodoScope, id: &str, req: UpdateTodoRequest, now: i64, expected: &VersionMatch) -> Result<Statement> {
+        let stmt = Statement::new(
+            "UPDATE todos SET title = COALESCE(?1, title), description = COALESCE(?2, description),
+             completed = COALESCE(?3, completed), priority = COALESCE(?4, priority),
+             due_date = COALESCE(?5, due_date), updated_at = ?6, version = version + 1
+             WHERE id = ?7 AND (?8 IS NULL OR owner_key_id = ?8)
+             AND (?9 IS NULL OR version IN (SELECT value FROM json_each(?9)))
+             RETURNING *"
+        );
+        
+        // None binds as NULL, which COALESCE turns back into the current column value
+        Ok(stmt.bind(&[
+            req.title.into(),
+            req.description.into(),
+            req.completed.map(i32::from).into(),
+            req.priority.into(),
+            req.due_date.into(),
+            now.into(),
+            id.into(),
+            scope.owner_param(),
+            expected.param()?,
+        ]))
+    }
+
+    // Apply a validated JSON Merge Patch; only the fields present in the patch are assigned
+    pub async fn patch_todo(&self, scope: &TodoScope, id: &str, patch: &TodoPatch, expected: &VersionMatch) -> Result<TodoWrite<TodoResponse>> {
+        // Nothing to write: report the current state, still honoring the precondition
+        if patch.is_empty() {
+            return match self.get_todo_row(scope, id).await? {
+                Some(row) if expected.accepts(row.version) => Ok(TodoWrite::Written(self.attach_tags_one(row.into()).await?)),
+                Some(row) => Ok(TodoWrite::VersionMismatch { current_version: row.version }),
+                None => Ok(TodoWrite::NotFound),
+            };
+        }
+        
+        let now = Self::current_timestamp();
+        let mut params: Vec<SqlValue> = Vec::new();
+        let mut assignments: Vec<String> = Vec::new();
+        
+        if let Some(title) = &patch.title {
+            assignments.push(format!("title = {}", bind_param(&mut params, title.as_str().into())));
+        }
+        if let Some(description) = &patch.description {
+            assignments.push(format!("description = {}", bind_param(&mut params, description.as_deref().into())));
+        }
+        if let Some(completed) = patch.completed {
+            assignments.push(format!("completed = {}", bind_param(&mut params, i32::from(completed).into())));
+        }
+        if let Some(priority) = patch.priority {
+            assignments.push(format!("priority = {}", bind_param(&mut params, priority.into())));
+        }
+        if let Some(due_date) = patch.due_date {
+            assignments.push(format!("due_date = {}", bind_param(&mut params, due_date.into())));
+        }
+        assignments.push(format!("updated_at = {}", bind_param(&mut params, now.into())));
+        assignments.push("version = version + 1".to_string());
+        
+        let id_param = bind_param(&mut params, id.into());
+        let owner_param = bind_param(&mut params, scope.owner_param());
+        let version_param = bind_param(&mut params, expected.param()?);
+        let sql = format!(
+            "UPDATE todos SET {} WHERE id = {id_param} AND ({owner_param} IS NULL OR owner_key_id = {owner_param})
+             AND ({version_param} IS NULL OR version IN (SELECT value FROM json_each({version_param})))
+             RETURNING *",
+            assignments.join(", ")
+        );
+        
+        let update = Statement::new(sql).bind(&params);
+        self.run_todo_write(scope, id, update, patch.tags.as_deref(), now, expected).await
+    }
+
+    // Run a conditional `UPDATE ... RETURNING *`, replacing tags in the same batch when given
+    async fn run_todo_write(&self, scope: &TodoScope, id: &str, update: Statement, tags: Option<&[String]>, now: i64, expected: &VersionMatch) -> Result<TodoWrite<TodoResponse>> {
+        match tags {
+            // Tag statements carry the same precondition and run first in the batch,
+            // so either the whole write applies or none of it does
+            Some(tags) => {
+                let mut statements = self.set_tags_statements(id, tags, now, scope, expected)?;
+                statements.push(update);
+                let results = self.storage.batch(statements).await?;
+                let row = match results.last() {
+                    Some(result) => result.rows::<TodoRow>()?.into_iter().next(),
+                    None => None,
+                };
+                match row {
+                    Some(row) => Ok(TodoWrite::Written(TodoResponse { tags: tags.to_vec(), ..row.into() })),
+                    None => self.write_failure(scope, id).await,
+                }
+            },
+            None => match self.first::<TodoRow>(update).await? {
+                Some(row) => Ok(TodoWrite::Written(self.attach_tags_one(row.into()).await?)),
+                None => self.write_failure(scope, id).await,
+            },
+        }
+    }
+
+    // Flip completed in place, so concurrent toggles each apply instead of cancelling out
+    pub async fn toggle_todo(&self, scope: &TodoScope, id: &str, expected: &VersionMatch) -> Result<TodoWrite<TodoResponse>> {
+        let row = self.first::<TodoRow>(self.toggle_todo_statement(scope, id, Self::current_timestamp(), expected)?).await?;
+        
+        match row {
+            Some(row) => Ok(TodoWrite::Written(self.attach_tags_one(row.into()).await?)),
+            None => self.write_failure(scope, id).await,
+        }
+    }
+
+    fn toggle_todo_statement(&self, scope: &TodoScope, id: &str, now: i64, expected: &VersionMatch) -> Result<Statement> {
+        let stmt = Statement::new(
+            "UPDATE todos SET completed = 1 - completed, updated_at = ?1, version = version + 1
+             WHERE id = ?2 AND (?3 IS NULL OR owner_key_id = ?3)
+             AND (?4 IS NULL OR version IN (SELECT value FROM json_each(?4)))
+             RETURNING *"
+        );
+        
+        Ok(stmt.bind(&[
+            now.into(),
+            id.into(),
+            scope.owner_param(),
+            expected.param()?,
+        ]))
+    }
+
+    // Tag links are removed by ON DELETE CASCADE on todo_tags
+    pub async fn delete_todo(&self, scope: &TodoScope, id: &str, expected: &VersionMatch) -> Result<TodoWrite<()>> {
+        // Only report success when a row was actually removed (other owners' ids must look missing)
+        let changes = self.run(self.delete_todo_statement(scope, id, expected)?).await?;
+        if changes > 0 {
+            Ok(TodoWrite::Written(()))
+        } else {
+            self.write_failure(scope, id).await
+        }
+    }
+
+    fn delete_todo_statement(&self, scope: &TodoScope, id: &str, expected: &VersionMatch) -> Result<Statement> {
+        let stmt = Statement::new(
+            "DELETE FROM todos WHERE id = ?1 AND (?2 IS NULL OR owner_key_id = ?2)
+             AND (?3 IS NULL OR version IN (SELECT value FROM json_each(?3)))"
+        );
+        Ok(stmt.bind(&[id.into(), scope.owner_param(), expected.param()?]))
+    }
+
+    // A conditional write matched no row: tell a missing todo apart from a stale If-Match
+    async fn write_failure<T>(&self, scope: &TodoScope, id: &str) -> Result<TodoWrite<T>> {
+        match self.get_todo_row(scope, id).await? {
+            Some(row) => Ok(TodoWrite::VersionMismatch { current_version: row.version }),
+            None => Ok(TodoWrite::NotFound),
+        }
+    }
+
+    // Run a list of operations as one D1 batch: either all of them apply or none do
+    // Targets are checked up front for a per-operation report; a guard statement at the head
+    // of the batch re-checks their versions so a concurrent write in between aborts the batch.
+    pub async fn execute_batch(&self, owner_key_id: &str, scope: &TodoScope, operations: Vec<BatchOperation>) -> Result<BatchResponse> {
+        let mut target_ids: Vec<&str> = operations.iter()
+            .filter_map(|op| op.target().map(|(id, _)| id))
+            .collect();
+        target_ids.sort_unstable();
+        target_ids.dedup();
+        
+        let current = self.todo_versions(scope, &target_ids).await?;
+        
+        let mut results: Vec<BatchOperationResult> = operations.iter()
+            .enumerate()
+            .map(|(index, op)| BatchOperationResult {
+                index,
+                op: op.name().to_string(),
+                id: op.target().map(|(id, _)| id.to_string()),
+                status: BatchStatus::Ok,
+                todo: None,
+                error: None,
+            })
+            .collect();
+        
+        let mut deleted: HashSet<&str> = HashSet::new();
+        for (result, op) in results.iter_mut().zip(&operations) {
+            let Some((id, pinned_version)) = op.target() else { continue };
+            
+            if deleted.contains(id) {
+                result.status = BatchStatus::Invalid;
+                result.error = Some("Todo is deleted by an earlier operation in this batch".to_string());
+            } else {
+                match current.get(id) {
+                    None => result.status = BatchStatus::NotFound,
+                    Some(version) if pinned_version.is_some_and(|pinned| pinned != *version) => {
+                        result.status = BatchStatus::VersionMismatch;
+                        result.error = Some(format!("Current version is {}", version));
+                    },
+                    Some(_) => {},
+                }
+            }
+            
+            if matches!(op, BatchOperation::Delete { .. }) {
+                deleted.insert(id);
+            }
+        }
+        
+        if results.iter().any(|result| result.status != BatchStatus::Ok) {
+            return Ok(Self::batch_not_applied(results, None));
+        }
+        
+        let now = Self::current_timestamp();
+        let mut statements = Vec::new();
+        
+        if !current.is_empty() {
+            let expected: Vec<(&String, &i64)> = current.iter().collect();
+            let guard = Statement::new(
+                "INSERT INTO write_preconditions (ok)
+                 SELECT COUNT(*) = ?2 FROM todos WHERE (?3 IS NULL OR owner_key_id = ?3)
+                 AND (id, version) IN (SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]') FROM json_each(?1))"
+            );
+            statements.push(guard.bind(&[
+                serde_json::to_string(&expected)?.into(),
+                expected.len().into(),
+                scope.owner_param(),
+            ]));
+        }
+        
+        // Index of the statement whose RETURNING row is each operation's resulting todo
+        let mut returning: Vec<Option<usize>> = Vec::with_capacity(results.len());
+        for (result, op) in results.iter_mut().zip(operations) {
+            match op {
+                BatchOperation::Create { todo } => {
+                    let (created, create) = self.create_todo_statements(owner_key_id, todo.todo, &todo.tags, now)?;
+                    result.id = Some(created.todo.id.clone());
+                    result.todo = Some(created);
+                    statements.extend(create);
+                    returning.push(None);
+                },
+                BatchOperation::Update { id, changes, .. } => {
+                    if let Some(tags) = &changes.tags {
+                        statements.extend(self.set_tags_statements(&id, tags, now, scope, &VersionMatch::Any)?);
+                    }
+                    statements.push(self.update_todo_statement(scope, &id, changes.todo, now, &VersionMatch::Any)?);
+                    returning.push(Some(statements.len() - 1));
+                },
+                BatchOperation::Delete { id, .. } => {
+                    statements.push(self.delete_todo_statement(scope, &id, &VersionMatch::Any)?);
+                    returning.push(None);
+                },
+                BatchOperation::Toggle { id, .. } => {
+                    statements.push(self.toggle_todo_statement(scope, &id, now, &VersionMatch::Any)?);
+                    returning.push(Some(statements.len() - 1));
+                },
+            }
+        }
+        
+        let batch_results = match self.storage.batch(statements).await {
+            Ok(batch_results) => batch_results,
+            Err(e) if e.to_string().contains(WRITE_PRECONDITION_FAILED) => {
+                let error = "Todos changed while the batch was running; retry".to_string();
+                return Ok(Self::batch_not_applied(results, Some(error)));
+            },
+            Err(e) => return Err(e),
+        };
+        
+        // Load tags for every updated or toggled todo with one query
+        let mut written: Vec<(usize, TodoResponse)> = Vec::new();
+        for (position, statement_index) in returning.iter().enumerate() {
+            let Some(statement_index) = statement_index else { continue };
+            let row = match batch_results.get(*statement_index) {
+                Some(batch_result) => batch_result.rows::<TodoRow>()?.into_iter().next(),
+                None => None,
+            };
+            if let Some(row) = row {
+                written.push((position, row.into()));
+            }
+        }
+        let (positions, todos): (Vec<usize>, Vec<TodoResponse>) = written.into_iter().unzip();
+        for (position, todo) in positions.into_iter().zip(self.attach_tags(todos).await?) {
+            results[position].todo = Some(todo);
+        }
+        
+        Ok(BatchResponse { success: true, results })
+    }
+
+    // Nothing was written: operations that were fine on their own are reported as not applied
+    fn batch_not_applied(mut results: Vec<BatchOperationResult>, error: Option<String>) -> BatchResponse {
+        for result in &mut results {
+            if result.status == BatchStatus::Ok {
+                result.status = BatchStatus::NotApplied;
+                result.error = error.clone();
+            }
+        }
+        BatchResponse { success: false, results }
+    }
+
+    // The given todos that are visible in the scope, with their tags
+    pub async fn get_todos(&self, scope: &TodoScope, ids: &[&str]) -> Result<Vec<TodoResponse>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        let stmt = Statement::new(
+            "SELECT * FROM todos
+             WHERE id IN (SELECT value FROM json_each(?1)) AND (?2 IS NULL OR owner_key_id = ?2)"
+        );
+        let rows: Vec<TodoRow> = self.all::<TodoRow>(stmt.bind(&[serde_json::to_string(ids)?.into(), scope.owner_param()])).await?;
+        self.attach_tags(rows.into_iter().map(Into::into).collect()).await
+    }
+
+    // Current versions of the given todos that are visible in the scope
+    async fn todo_versions(&self, scope: &TodoScope, ids: &[&str]) -> Result<HashMap<String, i64>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        
+        let stmt = Statement::new(
+            "SELECT id, version FROM todos
+             WHERE id IN (SELECT value FROM json_each(?1)) AND (?2 IS NULL OR owner_key_id = ?2)"
+        );
+        let rows: Vec<IdVersionRow> = self.all::<IdVersionRow>(stmt.bind(&[serde_json::to_string(ids)?.into(), scope.owner_param()])).await?;
+        Ok(rows.into_iter().map(|row| (row.id, row.version)).collect())
+    }
+
+    // Full-text search over title and description, ranked by bm25 (title matches weigh more)
+    // The query uses FTS5 syntax: "exact phrase", prefix*, AND / OR / NOT and parentheses.
+    // Queries FTS5 cannot parse are retried as literal terms instead of failing the request.
+    pub async fn search_todos(&self, scope: &TodoScope, query: &str, page: &PageRequest<SearchCursor>) -> Result<Page<SearchResult>> {
+        let rows = match self.query_search(scope, query, page).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                let literal = literal_fts_query(query);
+                if literal.is_empty() || literal == query {
+                    return Err(e);
+                }
+                self.query_search(scope, &literal, page).await?
+            },
+        };
+        
+        let rows = Page::from_rows(rows, page.limit, |row: &SearchRow| SearchCursor {
+            score: row.score,
+            id: row.id.clone(),
+        });
+        
+        let (todos, ranking): (Vec<TodoResponse>, Vec<(f64, SearchHighlights)>) = rows.items.into_iter()
+            .map(|row| {
+                let (todo, score, highlights) = row.into_parts();
+                (todo, (score, highlights))
+            })
+            .unzip();
+        let todos = self.attach_tags(todos).await?;
+        
+        // Expose relevance as higher-is-better; bm25 itself is negative with lower meaning better
+        Ok(Page {
+            items: todos.into_iter()
+                .zip(ranking)
+                .map(|(todo, (score, highlights))| SearchResult { todo, score: -score, highlights })
+                .collect(),
+            next_cursor: rows.next_cursor,
+        })
+    }
+
+    async fn query_search(&self, scope: &TodoScope, match_expr: &str, page: &PageRequest<SearchCursor>) -> Result<Vec<SearchRow>> {
+        // bm25 weights follow the FTS column order: todo_id (unindexed), title, description
+        let stmt = Statement::new(
+            "SELECT * FROM (
+                SELECT todos.*, bm25(todos_fts, 0.0, 10.0, 1.0) AS score,
+                       highlight(todos_fts, 1, '<mark>', '</mark>') AS title_highlight,
+                       snippet(todos_fts, 2, '<mark>', '</mark>', '…', 16) AS description_snippet
+                FROM todos_fts JOIN todos ON todos.id = todos_fts.todo_id
+                WHERE todos_fts MATCH ?1 AND (?2 IS NULL OR todos.owner_key_id = ?2)
+             )
+             WHERE (?3 IS NULL OR score > ?3 OR (score = ?3 AND id > ?4))
+             ORDER BY score, id
+             LIMIT ?5"
+        );
+        
+        let (after_score, after_id) = match &page.after {
+            Some(cursor) => (cursor.score.into(), cursor.id.as_str().into()),
+            None => (SqlValue::Null, SqlValue::Null),
+        };
+        
+        self.all::<SearchRow>(stmt.bind(&[
+            match_expr.into(),
+            scope.owner_param(),
+            after_score,
+            after_id,
+            page.fetch_limit().into(),
+        ])).await
+    }
+
+    // Todos changed and deleted after the `since` sequence, oldest first, at most `limit` entries
+    // Sequences come from one counter shared by both tables, so merging the two streams is exact
+    pub async fn sync_changes(&self, scope: &TodoScope, since: i64, limit: usize) -> Result<SyncResponse> {
+        let fetch_limit = limit + 1;
+        
+        let stmt = Statement::new(
+            "SELECT * FROM todos WHERE change_seq > ?1 AND (?2 IS NULL OR owner_key_id = ?2)
+             ORDER BY change_seq LIMIT ?3"
+        );
+        let todo_rows: Vec<TodoRow> = self.all::<TodoRow>(stmt.bind(&[since.into(), scope.owner_param(), fetch_limit.into()])).await?;
+        
+        let stmt = Statement::new(
+            "SELECT todo_id, change_seq, deleted_at FROM todo_tombstones
+             WHERE change_seq > ?1 AND (?2 IS NULL OR owner_key_id = ?2)
+             ORDER BY change_seq LIMIT ?3"
+        );
+        let tombstone_rows: Vec<TombstoneRow> = self.all::<TombstoneRow>(stmt.bind(&[since.into(), scope.owner_param(), fetch_limit.into()])).await?;
+        
+        // Keep the `limit` oldest changes across both streams; the token points at the last one kept
+        let mut seqs: Vec<i64> = todo_rows.iter().map(|row| row.change_seq)
+            .chain(tombstone_rows.iter().map(|row| row.change_seq))
+            .collect();
+        seqs.sort_unstable();
+        let has_more = seqs.len() > limit;
+        seqs.truncate(limit);
+        let next_seq = seqs.last().copied().unwrap_or(since);
         
+        let todos: Vec<TodoResponse> = todo_rows.into_iter()
+            .filter(|row| row.change_seq <= next_seq)
+            .map(Into::into)
+            .collect();
+        let deleted = tombstone_rows.into_iter()
+            .filter(|row| row.change_seq <= next_seq)
+            .map(|row| Tombstone { id: row.todo_id, deleted_at: row.deleted_at })
+            .collect();
+        
+        Ok(SyncResponse {
+            changed: self.attach_tags(todos).await?,
+            deleted,
+            next_token: next_seq.to_string(),
+            has_more,
+        })
+    }
+
+    // Statements that replace a todo's tags: create missing tags for the todo's owner, then relink
+    // Tag lists are bound as JSON arrays and expanded with json_each to keep the SQL static.
+    // Each statement is a no-op unless the todo passes the scope and version precondition.
+    fn set_tags_statements(&self, todo_id: &str, tags: &[String], now: i64, scope: &TodoScope, expected: &VersionMatch) -> Result<Vec<Statement>> {
+        let tags_json = serde_json::to_string(tags)?;
+        
+        let create_tags = Statement::new(
+            "INSERT OR IGNORE INTO tags (id, owner_key_id, name, created_at)
+             SELECT lower(hex(randomblob(16))), todos.owner_key_id, tag.value, ?2
+             FROM todos, json_each(?3) AS tag
+             WHERE todos.id = ?1 AND (?4 IS NULL OR todos.owner_key_id = ?4)
+             AND (?5 IS NULL OR todos.version IN (SELECT value FROM json_each(?5)))"
+        ).bind(&[
+            todo_id.into(),
+            now.into(),
+            tags_json.clone().into(),
+            scope.owner_param(),
+            expected.param()?,
+        ]);
+        
+        let unlink = Statement::new(
+            "DELETE FROM todo_tags WHERE todo_id = ?1 AND EXISTS (
+                SELECT 1 FROM todos WHERE id = ?1 AND (?2 IS NULL OR owner_key_id = ?2)
+                AND (?3 IS NULL OR version IN (SELECT value FROM json_each(?3)))
+             )"
+        ).bind(&[todo_id.into(), scope.owner_param(), expected.param()?]);
+        
+        let link = Statement::new(
+            "INSERT INTO todo_tags (todo_id, tag_id)
+             SELECT todos.id, tags.id FROM todos JOIN tags ON tags.owner_key_id = todos.owner_key_id
+             WHERE todos.id = ?1 AND tags.name IN (SELECT value FROM json_each(?2))
+             AND (?3 IS NULL OR todos.owner_key_id = ?3)
+             AND (?4 IS NULL OR todos.version IN (SELECT value FROM json_each(?4)))"
+        ).bind(&[todo_id.into(), tags_json.into(), scope.owner_param(), expected.param()?]);
+        
+        Ok(vec![create_tags, unlink, link])
+    }
+
+    async fn attach_tags_one(&self, todo: TodoResponse) -> Result<TodoResponse> {
+        let mut todos = self.attach_tags(vec![todo]).await?;
+        todos.pop().ok_or_else(|| Error::RustError("Todo lost while loading tags".to_string()))
+    }
+
+    // Load tags for a page of todos with a single query
+    async fn attach_tags(&self, mut todos: Vec<TodoResponse>) -> Result<Vec<TodoResponse>> {
+        if todos.is_empty() {
+            return Ok(todos);
+        }
+        
+        let ids: Vec<&str> = todos.iter().map(|todo| todo.todo.id.as_str()).collect();
+        let stmt = Statement::new(
+            "SELECT tt.todo_id, t.name FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id
+             WHERE tt.todo_id IN (SELECT value FROM json_each(?1))
+             ORDER BY t.name"
+        );
+        
+        let rows: Vec<TodoTagRow> = self.all::<TodoTagRow>(stmt.bind(&[serde_json::to_string(&ids)?.into()])).await?;
+        
+        let mut tags_by_todo: HashMap<String, Vec<String>> = HashMap::new();
+        for row in rows {
+            tags_by_todo.entry(row.todo_id).or_default().push(row.name);
+        }
+        
+        // A todo can appear more than once (e.g. batch results), so tags are copied, not moved
+        for todo in &mut todos {
+            todo.tags = tags_by_todo.get(&todo.todo.id).cloned().unwrap_or_default();
+        }
         Ok(todos)
     }
 
+    // Tags visible in the scope with the number of todos using each
+    pub async fn list_tags(&self, scope: &TodoScope) -> Result<Vec<TagInfo>> {
+        let stmt = Statement::new(
+            "SELECT t.name AS name, COUNT(tt.todo_id) AS todo_count
+             FROM tags t LEFT JOIN todo_tags tt ON tt.tag_id = t.id
+             WHERE (?1 IS NULL OR t.owner_key_id = ?1)
+             GROUP BY t.name ORDER BY t.name"
+        );
+        
+        self.all::<TagInfo>(stmt.bind(&[scope.owner_param()])).await
+    }
+
+    // Rename a tag on every todo in the scope; if the new name already exists the tags merge
+    // Returns None when no tag with the old name exists
+    pub async fn rename_tag(&self, scope: &TodoScope, from: &str, to: &str) -> Result<Option<TagInfo>> {
+        let exists = self.first::<serde_json::Value>(Statement::new(
+            "SELECT COUNT(*) AS count FROM tags WHERE name = ?1 AND (?2 IS NULL OR owner_key_id = ?2)"
+        ).bind(&[from.into(), scope.owner_param()]))
+        .await?
+        .and_then(|value| value.get("count").and_then(serde_json::Value::as_f64))
+        .unwrap_or(0.0) > 0.0;
+        
+        if !exists {
+            return Ok(None);
+        }
+        
+        let now: SqlValue = Self::current_timestamp().into();
+        
+        // Make sure every owner of the old tag has the target tag
+        let create_target = Statement::new(
+            "INSERT OR IGNORE INTO tags (id, owner_key_id, name, created_at)
+             SELECT lower(hex(randomblob(16))), owner_key_id, ?1, ?2 FROM tags
+             WHERE name = ?3 AND (?4 IS NULL OR owner_key_id = ?4)"
+        ).bind(&[to.into(), now.clone(), from.into(), scope.owner_param()]);
+        
+        // Every todo carrying the old tag changes, so it gets a new version (and ETag) like any write
+        let bump_todos = Statement::new(
+            "UPDATE todos SET updated_at = ?1, version = version + 1
+             WHERE id IN (SELECT tt.todo_id FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id
+                          WHERE t.name = ?2 AND (?3 IS NULL OR t.owner_key_id = ?3))"
+        ).bind(&[now, from.into(), scope.owner_param()]);
+        
+        // Move links over (todos already carrying both keep a single link)
+        let relink = Statement::new(
+            "INSERT OR IGNORE INTO todo_tags (todo_id, tag_id)
+             SELECT tt.todo_id, target.id FROM todo_tags tt
+             JOIN tags source ON source.id = tt.tag_id
+             JOIN tags target ON target.owner_key_id = source.owner_key_id AND target.name = ?1
+             WHERE source.name = ?2 AND (?3 IS NULL OR source.owner_key_id = ?3)"
+        ).bind(&[to.into(), from.into(), scope.owner_param()]);
+        
+        // Links to the old tag go with it via ON DELETE CASCADE
+        let drop_source = Statement::new(
+            "DELETE FROM tags WHERE name = ?1 AND (?2 IS NULL OR owner_key_id = ?2)"
+        ).bind(&[from.into(), scope.owner_param()]);
+        
+        self.storage.batch(vec![create_target, bump_todos, relink, drop_source]).await?;
+        
+        let todo_count = self.first::<serde_json::Value>(Statement::new(
+            "SELECT COUNT(DISTINCT tt.todo_id) AS count FROM todo_tags tt JOIN tags t ON t.id = tt.tag_id
+             WHERE t.name = ?1 AND (?2 IS NULL OR t.owner_key_id = ?2)"
+        ).bind(&[to.into(), scope.owner_param()]))
+        .await?
+        .and_then(|value| value.get("count").and_then(serde_json::Value::as_f64))
+        .unwrap_or(0.0);
+        
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(Some(TagInfo { name: to.to_string(), todo_count: todo_count as i64 }))
+    }
+
     // Resolve partial ID prefix to full ID for efficient client-side operations
     // Returns matches with id and title for disambiguation
-    pub async fn resolve_id_prefix(&self, prefix: &str) -> Result<Vec<(String, String)>> {
-        let stmt = self.d1.prepare(
+    pub async fn resolve_id_prefix(&self, scope: &TodoScope, prefix: &str) -> Result<Vec<(String, String)>> {
+        let stmt = Statement::new(
             "SELECT id, title FROM todos 
-             WHERE id LIKE ?1 
+             WHERE id LIKE ?1 ESCAPE '\\' AND (?2 IS NULL OR owner_key_id = ?2)
              ORDER BY created_at DESC"
         );
         
         // Escape SQL wildcards to treat them as literal characters
         let escaped_prefix = prefix.replace('%', r"\%").replace('_', r"\_");
         let prefix_pattern = format!("{}%", escaped_prefix);
-        let results = stmt.bind(&[prefix_pattern.into()])?.all().await?;
+        let rows: Vec<IdTitleRow> = self.all::<IdTitleRow>(stmt.bind(&[prefix_pattern.into(), scope.owner_param()])).await?;
         
         // Use structured deserialization for consistency and safety
-        let rows: Vec<IdTitleRow> = results.results::<IdTitleRow>()?;
         let matches: Vec<(String, String)> = rows.into_iter()
             .map(|row| (row.id, row.title))
             .collect();
         
         Ok(matches)
     }
-}
\ No newline at end of file
+}
+
+// Run against an in-memory SQLite database migrated like a fresh D1 one
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::schema;
+    use crate::storage::{block_on, SqliteStorage};
+    use serde_json::json;
+
+    async fn database() -> Result<Database<SqliteStorage>> {
+        let db = Database::with_storage(SqliteStorage::open_in_memory()?);
+        schema::migrate(&db).await?;
+        Ok(db)
+    }
+
+    async fn client_key(db: &Database<SqliteStorage>, name: &str) -> Result<String> {
+        db.create_api_key(format!("hash-{name}"), format!("lookup-{name}"), name.to_string(), KeyType::Client, &ApiKeyOptions::default()).await
+    }
+
+    async fn todo(db: &Database<SqliteStorage>, owner: &str, title: &str) -> Result<TodoResponse> {
+        db.create_todo(owner, serde_json::from_value(json!({ "title": title }))?, &[]).await
+    }
+
+    fn update(title: &str) -> Result<UpdateTodoRequest> {
+        Ok(serde_json::from_value(json!({ "title": title }))?)
+    }
+
+    #[test]
+    fn todos_are_scoped_to_their_owner() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let alice = client_key(&db, "alice").await?;
+            let bob = client_key(&db, "bob").await?;
+            let id = todo(&db, &alice, "Alice's").await?.todo.id;
+            let as_bob = TodoScope::Owner(bob);
+            
+            assert!(db.get_todo(&as_bob, &id).await?.is_none());
+            assert!(matches!(db.update_todo(&as_bob, &id, update("Bob's")?, None, &VersionMatch::Any).await?, TodoWrite::NotFound));
+            assert!(matches!(db.delete_todo(&as_bob, &id, &VersionMatch::Any).await?, TodoWrite::NotFound));
+            
+            assert_eq!(db.get_todo(&TodoScope::Owner(alice), &id).await?.map(|todo| todo.todo.title), Some("Alice's".to_string()));
+            assert!(db.get_todo(&TodoScope::AllOwners, &id).await?.is_some());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn renaming_a_tag_bumps_the_version_of_its_todos() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let alice = client_key(&db, "alice").await?;
+            let scope = TodoScope::Owner(alice.clone());
+            let tagged = db.create_todo(&alice, serde_json::from_value(json!({ "title": "Tagged" }))?, &["wrok".to_string()]).await?;
+            let untagged = todo(&db, &alice, "Untagged").await?;
+            
+            assert!(db.rename_tag(&scope, "wrok", "work").await?.is_some());
+            
+            let renamed = db.get_todo(&scope, &tagged.todo.id).await?.expect("tagged todo");
+            assert_eq!((renamed.version, renamed.tags), (tagged.version + 1, vec!["work".to_string()]));
+            assert!(renamed.todo.updated_at >= tagged.todo.updated_at);
+            assert_eq!(db.get_todo(&scope, &untagged.todo.id).await?.map(|todo| todo.version), Some(untagged.version));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn keyset_cursor_pages_through_every_todo_once() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let alice = client_key(&db, "alice").await?;
+            let bob = client_key(&db, "bob").await?;
+            let mut created = Vec::new();
+            for index in 0..5 {
+                created.push(todo(&db, &alice, &format!("Todo {index}")).await?.todo.id);
+            }
+            todo(&db, &bob, "Not Alice's").await?;
+            
+            let scope = TodoScope::Owner(alice);
+            let mut page = PageRequest { limit: 2, after: None };
+            let mut seen = Vec::new();
+            let mut sizes = Vec::new();
+            loop {
+                let listed = db.list_todos(&scope, &TodoFilter::default(), &TodoSort::default(), &page).await?;
+                sizes.push(listed.items.len());
+                seen.extend(listed.items.into_iter().map(|todo| todo.todo.id));
+                let Some(cursor) = listed.next_cursor else { break };
+                let url = Url::parse(&format!("http://localhost/todos?limit=2&cursor={cursor}"))?;
+                page = PageRequest::from_url(&url).map_err(Error::RustError)?;
+            }
+            
+            assert_eq!(sizes, vec![2, 2, 1]);
+            seen.sort();
+            created.sort();
+            assert_eq!(seen, created);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn stale_if_match_is_a_version_mismatch() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let alice = client_key(&db, "alice").await?;
+            let created = todo(&db, &alice, "Draft").await?;
+            let scope = TodoScope::Owner(alice);
+            let id = created.todo.id;
+            
+            let stale = VersionMatch::OneOf(vec![created.version + 1]);
+            match db.update_todo(&scope, &id, update("Lost update")?, None, &stale).await? {
+                TodoWrite::VersionMismatch { current_version } => assert_eq!(current_version, created.version),
+                other => panic!("expected a version mismatch, got {other:?}"),
+            }
+            
+            let current = VersionMatch::OneOf(vec![created.version]);
+            match db.update_todo(&scope, &id, update("Final")?, None, &current).await? {
+                TodoWrite::Written(updated) => assert_eq!(updated.version, created.version + 1),
+                other => panic!("expected the update to apply, got {other:?}"),
+            }
+            assert!(matches!(db.delete_todo(&scope, &id, &current).await?, TodoWrite::VersionMismatch { .. }));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn failed_write_precondition_rolls_back_the_batch() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let alice = client_key(&db, "alice").await?;
+            let created = todo(&db, &alice, "Untouched").await?;
+            let scope = TodoScope::Owner(alice);
+            
+            let statements = vec![
+                Statement::new("UPDATE todos SET title = 'Changed' WHERE id = ?1").bind(&[created.todo.id.as_str().into()]),
+                Statement::new("INSERT INTO write_preconditions (ok) VALUES (0)"),
+            ];
+            let result = db.storage.batch(statements).await;
+            assert!(matches!(&result, Err(Error::RustError(message)) if message.contains(WRITE_PRECONDITION_FAILED)));
+            
+            let stored = db.get_todo(&scope, &created.todo.id).await?.map(|todo| (todo.todo.title, todo.version));
+            assert_eq!(stored, Some(("Untouched".to_string(), created.version)));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn batch_with_a_failing_operation_applies_nothing() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let alice = client_key(&db, "alice").await?;
+            let created = todo(&db, &alice, "Pinned").await?;
+            let scope = TodoScope::Owner(alice.clone());
+            
+            let operations = serde_json::from_value(json!([
+                { "op": "create", "todo": { "title": "Never created" } },
+                { "op": "update", "id": created.todo.id, "changes": { "title": "Never renamed" }, "version": created.version + 1 },
+            ]))?;
+            let response = db.execute_batch(&alice, &scope, operations).await?;
+            
+            assert!(!response.success);
+            let statuses: Vec<BatchStatus> = response.results.into_iter().map(|result| result.status).collect();
+            assert_eq!(statuses, vec![BatchStatus::NotApplied, BatchStatus::VersionMismatch]);
+            let listed = db.list_todos(&scope, &TodoFilter::default(), &TodoSort::default(), &PageRequest { limit: 10, after: None }).await?;
+            let titles: Vec<String> = listed.items.into_iter().map(|todo| todo.todo.title).collect();
+            assert_eq!(titles, vec!["Pinned".to_string()]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn sync_reports_deletions_to_the_owner_only() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let alice = client_key(&db, "alice").await?;
+            let bob = client_key(&db, "bob").await?;
+            let kept = todo(&db, &alice, "Kept").await?.todo.id;
+            let deleted = todo(&db, &alice, "Deleted").await?.todo.id;
+            let scope = TodoScope::Owner(alice);
+            
+            let before = db.sync_changes(&scope, 0, 50).await?;
+            assert!(matches!(db.delete_todo(&scope, &deleted, &VersionMatch::Any).await?, TodoWrite::Written(())));
+            
+            let since: i64 = before.next_token.parse().map_err(|_| Error::RustError("sync token is not a number".to_string()))?;
+            let after = db.sync_changes(&scope, since, 50).await?;
+            assert!(after.changed.is_empty());
+            assert_eq!(after.deleted.iter().map(|tombstone| tombstone.id.as_str()).collect::<Vec<_>>(), vec![deleted.as_str()]);
+            
+            let full = db.sync_changes(&scope, 0, 50).await?;
+            assert_eq!(full.changed.iter().map(|todo| todo.todo.id.as_str()).collect::<Vec<_>>(), vec![kept.as_str()]);
+            assert!(db.sync_changes(&TodoScope::Owner(bob), 0, 50).await?.deleted.is_empty());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn reinitialize_takes_over_only_what_the_replaced_admin_keys_own() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let first = db.create_api_key("hash-first".to_string(), "lookup-first".to_string(), "first".to_string(), KeyType::Admin, &ApiKeyOptions::default()).await?;
+            let second = db.create_api_key("hash-second".to_string(), "lookup-second".to_string(), "second".to_string(), KeyType::Admin, &ApiKeyOptions::default()).await?;
+            let retired = db.create_api_key("hash-retired".to_string(), "lookup-retired".to_string(), "retired".to_string(), KeyType::Admin, &ApiKeyOptions::default()).await?;
+            let work = vec!["work".to_string()];
+            db.create_todo(&first, serde_json::from_value(json!({ "title": "First's" }))?, &work).await?;
+            db.create_todo(&second, serde_json::from_value(json!({ "title": "Second's" }))?, &work).await?;
+            let orphaned = todo(&db, &retired, "Deliberately orphaned").await?.todo.id;
+            db.run(Statement::new("UPDATE api_keys SET active = 0 WHERE id = ?1").bind(&[retired.as_str().into()])).await?;
+            
+            let new_key = db.reinitialize_admin_keys("hash-new".to_string(), "lookup-new".to_string()).await?;
+            let scope = TodoScope::Owner(new_key);
+            
+            let listed = db.list_todos(&scope, &TodoFilter::default(), &TodoSort::default(), &PageRequest { limit: 10, after: None }).await?;
+            assert_eq!(listed.items.len(), 2);
+            assert!(listed.items.iter().all(|todo| todo.tags == work));
+            let tags = db.list_tags(&scope).await?;
+            assert_eq!(tags.iter().map(|tag| (tag.name.as_str(), tag.todo_count)).collect::<Vec<_>>(), vec![("work", 2)]);
+            assert!(db.get_todo(&TodoScope::Owner(retired), &orphaned).await?.is_some());
+            assert!(db.validate_api_key("hash-first").await?.is_none());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn reinitialize_takes_over_what_a_rotated_admin_key_works_on() -> Result<()> {
+        block_on(async {
+            let db = database().await?;
+            let original = db.create_api_key("hash-original".to_string(), "lookup-original".to_string(), "admin".to_string(), KeyType::Admin, &ApiKeyOptions::default()).await?;
+            let created = todo(&db, &original, "Before rotation").await?.todo.id;
+            let rotated = db.rotate_api_key(&original, "hash-rotated".to_string(), "lookup-rotated".to_string(), Utc::now().timestamp() - 1).await?;
+            assert!(rotated.is_some());
+            
+            let new_key = db.reinitialize_admin_keys("hash-new".to_string(), "lookup-new".to_string()).await?;
+            
+            assert!(db.get_todo(&TodoScope::Owner(new_key), &created).await?.is_some());
+            assert!(db.validate_api_key("hash-rotated").await?.is_none());
+            Ok(())
+        })
+    }
+}
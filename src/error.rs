@@ -0,0 +1,240 @@
+// Typed errors returned by route handlers
+// Each variant fixes its HTTP status and a stable machine-readable code; the request pipeline
+// (middleware.rs) turns them into the JSON error envelope, so handlers only say what went wrong.
+// Codes are part of the API contract (see API.md): add new ones freely, never rename existing ones.
+
+use std::fmt;
+#[allow(clippy::wildcard_imports)]
+use crate::platform::*;
+use serde_json::json;
+use crate::models::{ErrorResponse, FieldError, Scope, todo_etag};
+
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
+
+// Candidate todos listed in an ambiguous prefix error's details
+const MAX_PREFIX_CANDIDATES: usize = 10;
+
+#[derive(Debug)]
+pub enum ApiError {
+    // Malformed requests
+    InvalidJson,
+    MissingParameter(String),
+    InvalidParameter(String),
+    PriorityRange(String),
+    Validation(Vec<FieldError>),
+
+    // Authentication and authorization
+    InvalidApiKey,
+    AdminRequired,
+    ScopeRequired(Scope),
+
+    // Todos and tags
+    TodoNotFound,
+    PrefixNotFound(String),
+    AmbiguousPrefix { prefix: String, candidates: Vec<(String, String)> },
+    VersionMismatch { current_version: i64 },
+    TagNotFound(String),
+
+    // API keys
+    KeyNotFound,
+    KeyNotRotatable,
+    KeyReplaced { replaced_by: String },
+    LastAdminKey,
+
+    // Server lifecycle and emergency recovery
+    AlreadyInitialized,
+    NotInitialized,
+    InvalidRecoverySecret,
+    RecoveryDisabled,
+    RecoveryThrottled,
+
+    // Rate limiting (see rate_limit.rs)
+    RateLimited { retry_after_secs: u64 },
+
+    // Idempotency-Key handling
+    InvalidIdempotencyKey(String),
+    IdempotencyKeyReused,
+    IdempotencyInProgress,
+
+    SchemaOutdated { current_version: u32, expected_version: u32 },
+    DatabaseNotConfigured,
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> u16 {
+        match self {
+            ApiError::InvalidJson
+            | ApiError::MissingParameter(_)
+            | ApiError::InvalidParameter(_)
+            | ApiError::PriorityRange(_)
+            | ApiError::NotInitialized
+            | ApiError::InvalidIdempotencyKey(_) => 400,
+            ApiError::InvalidApiKey | ApiError::InvalidRecoverySecret => 401,
+            ApiError::AdminRequired | ApiError::ScopeRequired(_) | ApiError::RecoveryDisabled => 403,
+            ApiError::TodoNotFound | ApiError::PrefixNotFound(_) | ApiError::TagNotFound(_) | ApiError::KeyNotFound => 404,
+            ApiError::AmbiguousPrefix { .. }
+            | ApiError::KeyNotRotatable
+            | ApiError::KeyReplaced { .. }
+            | ApiError::LastAdminKey
+            | ApiError::AlreadyInitialized
+            | ApiError::IdempotencyInProgress => 409,
+            ApiError::VersionMismatch { .. } => 412,
+            ApiError::Validation(_) | ApiError::IdempotencyKeyReused => 422,
+            ApiError::RecoveryThrottled | ApiError::RateLimited { .. } => 429,
+            ApiError::DatabaseNotConfigured | ApiError::Internal(_) => 500,
+            ApiError::SchemaOutdated { .. } => 503,
+        }
+    }
+
+    // Stable identifier clients can branch on instead of matching messages
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidJson => "request.invalid_json",
+            ApiError::MissingParameter(_) => "request.missing_parameter",
+            ApiError::InvalidParameter(_) => "request.invalid_parameter",
+            ApiError::PriorityRange(_) => "validation.priority_range",
+            ApiError::Validation(_) => "validation.failed",
+            ApiError::InvalidApiKey => "auth.invalid_key",
+            ApiError::AdminRequired => "auth.admin_required",
+            ApiError::ScopeRequired(_) => "auth.scope_required",
+            ApiError::TodoNotFound => "todo.not_found",
+            ApiError::PrefixNotFound(_) => "todo.prefix_not_found",
+            ApiError::AmbiguousPrefix { .. } => "todo.ambiguous_prefix",
+            ApiError::VersionMismatch { .. } => "todo.version_mismatch",
+            ApiError::TagNotFound(_) => "tag.not_found",
+            ApiError::KeyNotFound => "key.not_found",
+            ApiError::KeyNotRotatable => "key.not_rotatable",
+            ApiError::KeyReplaced { .. } => "key.replaced",
+            ApiError::LastAdminKey => "key.last_admin",
+            ApiError::AlreadyInitialized => "server.already_initialized",
+            ApiError::NotInitialized => "server.not_initialized",
+            ApiError::InvalidRecoverySecret => "recovery.invalid_secret",
+            ApiError::RecoveryDisabled => "recovery.disabled",
+            ApiError::RecoveryThrottled => "recovery.throttled",
+            ApiError::RateLimited { .. } => "rate_limit.exceeded",
+            ApiError::InvalidIdempotencyKey(_) => "idempotency.invalid_key",
+            ApiError::IdempotencyKeyReused => "idempotency.key_reused",
+            ApiError::IdempotencyInProgress => "idempotency.in_progress",
+            ApiError::SchemaOutdated { .. } => "schema.outdated",
+            ApiError::DatabaseNotConfigured => "server.database_not_configured",
+            ApiError::Internal(_) => "server.internal",
+        }
+    }
+
+    // Structured context for clients, e.g. the candidates behind an ambiguous prefix
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            ApiError::PriorityRange(parameter) => Some(json!({ "parameter": parameter })),
+            ApiError::ScopeRequired(scope) => Some(json!({ "scope": scope.as_str() })),
+            ApiError::PrefixNotFound(prefix) => Some(json!({ "prefix": prefix })),
+            ApiError::AmbiguousPrefix { prefix, candidates } => Some(json!({
+                "prefix": prefix,
+                "match_count": candidates.len(),
+                "candidates": candidates.iter()
+                    .take(MAX_PREFIX_CANDIDATES)
+                    .map(|(id, title)| json!({ "id": id, "title": title }))
+                    .collect::<Vec<_>>(),
+            })),
+            ApiError::VersionMismatch { current_version } => Some(json!({ "current_version": current_version })),
+            ApiError::TagNotFound(tag) => Some(json!({ "tag": tag })),
+            ApiError::KeyReplaced { replaced_by } => Some(json!({ "replaced_by": replaced_by })),
+            ApiError::RateLimited { retry_after_secs } => Some(json!({ "retry_after": retry_after_secs })),
+            ApiError::SchemaOutdated { current_version, expected_version } => Some(json!({
+                "current_version": current_version,
+                "expected_version": expected_version,
+            })),
+            _ => None,
+        }
+    }
+
+    pub fn to_response(&self, request_id: &str) -> Result<Response> {
+        let mut body = ErrorResponse::new(self.to_string(), self.code(), self.details(), request_id);
+        if let ApiError::Validation(fields) = self {
+            body.fields = fields.clone();
+        }
+        
+        let mut response = Response::from_json(&body)?.with_status(self.status());
+        // The current ETag tells the client what to refetch
+        if let ApiError::VersionMismatch { current_version } = self {
+            response.headers_mut().set("ETag", &todo_etag(*current_version))?;
+        }
+        if let ApiError::RateLimited { retry_after_secs } = self {
+            response.headers_mut().set("Retry-After", &retry_after_secs.to_string())?;
+        }
+        Ok(response)
+    }
+}
+
+// Wrap a failed operation as a 500, e.g. `.map_err(internal("Failed to create todo"))`
+pub fn internal<E: fmt::Display>(context: &'static str) -> impl FnOnce(E) -> ApiError {
+    move |e| ApiError::Internal(format!("{}: {}", context, e))
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidJson => f.write_str("Invalid JSON body"),
+            ApiError::MissingParameter(message)
+            | ApiError::InvalidParameter(message)
+            | ApiError::InvalidIdempotencyKey(message)
+            | ApiError::Internal(message) => f.write_str(message),
+            ApiError::PriorityRange(parameter) => write!(f, "{} must be an integer between 1 and 5", parameter),
+            ApiError::Validation(_) => f.write_str("Validation failed"),
+            ApiError::InvalidApiKey => f.write_str("Invalid or missing API key"),
+            ApiError::AdminRequired => f.write_str("Admin privileges required"),
+            ApiError::ScopeRequired(scope) => write!(f, "API key lacks the '{}' scope", scope.as_str()),
+            ApiError::TodoNotFound => f.write_str("Todo not found"),
+            ApiError::PrefixNotFound(prefix) => write!(f, "No todo found with prefix '{}'", prefix),
+            ApiError::VersionMismatch { current_version } => {
+                write!(f, "Todo was modified by another client (current version {})", current_version)
+            },
+            ApiError::AmbiguousPrefix { prefix, candidates } => {
+                // Name the first few matches so a human can pick one
+                let shown: Vec<String> = candidates.iter()
+                    .take(3)
+                    .map(|(id, title)| format!("{} ({})", &id[..id.len().min(8)], title))
+                    .collect();
+                write!(
+                    f,
+                    "Ambiguous prefix '{}' matches {} todos: {}{}",
+                    prefix,
+                    candidates.len(),
+                    shown.join(", "),
+                    if candidates.len() > 3 { ", ..." } else { "" }
+                )
+            },
+            ApiError::TagNotFound(tag) => write!(f, "Tag '{}' not found", tag),
+            ApiError::KeyNotFound => f.write_str("API key not found"),
+            ApiError::KeyNotRotatable => f.write_str("API key is revoked, expired or already rotated"),
+            ApiError::KeyReplaced { .. } => f.write_str("API key was replaced by a rotation; use its successor instead"),
+            ApiError::LastAdminKey => f.write_str("Cannot revoke or shorten the expiry of the last active admin key that can manage keys"),
+            ApiError::AlreadyInitialized => f.write_str("Server already initialized"),
+            ApiError::NotInitialized => f.write_str("Server not initialized. Use POST /initialize first"),
+            ApiError::InvalidRecoverySecret => f.write_str("Invalid or missing recovery secret"),
+            ApiError::RecoveryDisabled => {
+                f.write_str("Emergency reinitialization is disabled: RECOVERY_SECRET is not configured")
+            },
+            ApiError::RecoveryThrottled => f.write_str("Too many failed reinitialization attempts. Try again later"),
+            ApiError::RateLimited { retry_after_secs } => {
+                write!(f, "Rate limit exceeded. Try again in {} second(s)", retry_after_secs)
+            },
+            ApiError::IdempotencyKeyReused => f.write_str("Idempotency-Key was already used for a different request"),
+            ApiError::IdempotencyInProgress => f.write_str("A request with this Idempotency-Key is still in progress"),
+            ApiError::SchemaOutdated { current_version, expected_version } => write!(
+                f,
+                "Database schema is at version {} but this server requires version {}. \
+                 Run `wrangler d1 migrations apply` or POST /admin/schema/migrate with an admin key.",
+                current_version, expected_version
+            ),
+            ApiError::DatabaseNotConfigured => f.write_str("Database not configured"),
+        }
+    }
+}
+
+// Platform errors (bad headers, serialization, D1 failures not mapped by the handler) are 500s
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
@@ -1,609 +1,900 @@
 // HTTP endpoint handlers for the Pali todo server API
-// Authentication is fully integrated with security logging
-// TODO: Add request validation middleware
-// TODO: Implement rate limiting per API key
+// Authentication, role checks, rate limiting and error responses come from the request pipeline (middleware.rs)
 
-use worker::*;
+#[allow(clippy::wildcard_imports)]
+use crate::platform::*;
 #[allow(clippy::wildcard_imports)]
 use crate::models::*;
-use crate::db::Database;
-use crate::auth::{validate_api_key_from_request, is_admin};
+use crate::db::{TodoFilter, TodoSort, TodoCursor, SearchCursor, AuditCursor, AuditFilter, ApiKeyOptions, VersionMatch, TodoWrite, SortField, SortDirection, TagMatch};
+use crate::pagination::{PageRequest, PagedResponse, parse_limit};
+use crate::schema;
+use crate::auth::{client_ip, verify_recovery_secret, RecoveryCheck, invalidate_cached_key, invalidate_cached_admin_keys, rotation_grace_secs, MAX_ROTATION_GRACE_SECS};
+use crate::error::{ApiError, ApiResult, internal};
+use crate::middleware::{Authenticated, RequestInfo, database, param, json_body, optional_json_body};
+use crate::validation::{TodoLimits, in_batch};
+use crate::rate_limit;
+use crate::audit::{self, AuditAction, AuditContext, todo_changes};
+use std::collections::HashMap;
+use serde_json::json;
+
+// Upper bound on operations in one POST /todos/batch request
+const MAX_BATCH_OPERATIONS: usize = 100;
+
+// Failed reinitialization attempts allowed per client IP within the throttle window
+const MAX_FAILED_RECOVERY_ATTEMPTS: u32 = 5;
+const RECOVERY_THROTTLE_WINDOW_SECS: i64 = 15 * 60;
+
+// POST /admin/keys/purge without revoked_before removes keys revoked longer ago than this
+const DEFAULT_PURGE_AFTER_SECS: i64 = 30 * 24 * 60 * 60;
+
+// Parse If-Match for todo writes; a missing header or "*" accepts any version
+// Only strong ETags can match (RFC 9110), so weak or malformed entries never do
+fn parse_if_match(req: &Request) -> Result<VersionMatch> {
+    let header = match req.headers().get("If-Match")? {
+        Some(header) => header,
+        None => return Ok(VersionMatch::Any),
+    };
+    
+    if header.trim() == "*" {
+        return Ok(VersionMatch::Any);
+    }
+    
+    let versions = header.split(',')
+        .filter_map(|tag| tag.trim().strip_prefix('"')?.strip_suffix('"')?.parse::<i64>().ok())
+        .collect();
+    Ok(VersionMatch::OneOf(versions))
+}
 
-// Security logging helper
-fn log_auth_attempt(method: &str, path: &str, client_name: Option<&str>, success: bool) {
-    let status = if success { "SUCCESS" } else { "FAILED" };
-    let client = client_name.unwrap_or("unknown");
-    console_log!("AUTH {}: {} {} - client: {}", status, method, path, client);
+// Single-todo success response carrying the todo's ETag
+fn todo_response(todo: TodoResponse) -> ApiResult<Response> {
+    let etag = todo_etag(todo.version);
+    let mut response = Response::from_json(&ApiResponse::success(todo))?;
+    response.headers_mut().set("ETag", &etag)?;
+    Ok(response)
 }
 
+// Result of a conditional write; a missing todo or stale If-Match becomes the matching error
+fn written<T>(write: TodoWrite<T>) -> ApiResult<T> {
+    match write {
+        TodoWrite::Written(value) => Ok(value),
+        TodoWrite::NotFound => Err(ApiError::TodoNotFound),
+        TodoWrite::VersionMismatch { current_version } => Err(ApiError::VersionMismatch { current_version }),
+    }
+}
 
-// Simple sync handlers for basic routes
-pub fn root(_: Request, _: RouteContext<()>) -> Result<Response> {
-    Response::ok("Pali Server API v1.0 - Self-hosted todo management")
+// Record a todo update, unless nothing was written (e.g. an empty merge patch)
+async fn audit_todo_update(caller: &Authenticated, req: &Request, ctx: &RouteContext<RequestInfo>, before: Option<&TodoResponse>, after: &TodoResponse) {
+    if before.is_some_and(|before| before.version == after.version) {
+        return;
+    }
+    caller.audit(req, ctx, AuditAction::TodoUpdated, &after.todo.id, todo_changes(before, Some(after))).await;
 }
 
-pub fn health_check(_: Request, _: RouteContext<()>) -> Result<Response> {
-    Response::ok("OK")
+// Parse the filter and sort parameters accepted by GET /todos
+// Fails on the first malformed parameter
+fn parse_todo_query(url: &Url) -> ApiResult<(TodoFilter, TodoSort)> {
+    let param = |name: &str| url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned());
+    let parse_bool = |name: &str| -> ApiResult<Option<bool>> {
+        param(name)
+            .map(|value| value.parse::<bool>().map_err(|_| ApiError::InvalidParameter(format!("{} must be 'true' or 'false'", name))))
+            .transpose()
+    };
+    let parse_timestamp = |name: &str| -> ApiResult<Option<i64>> {
+        param(name)
+            .map(|value| value.parse::<i64>().map_err(|_| ApiError::InvalidParameter(format!("{} must be a Unix timestamp", name))))
+            .transpose()
+    };
+    let parse_priority = |name: &str| -> ApiResult<Option<i32>> {
+        match param(name).map(|value| value.parse::<i32>()) {
+            Some(Ok(priority)) if (1..=5).contains(&priority) => Ok(Some(priority)),
+            Some(_) => Err(ApiError::PriorityRange(name.to_string())),
+            None => Ok(None),
+        }
+    };
+    
+    // ?tag= may repeat; ?tag_match=any returns todos with any of the tags instead of all of them
+    let tags: Vec<String> = url.query_pairs()
+        .filter(|(key, _)| key == "tag")
+        .map(|(_, value)| value.into_owned())
+        .collect();
+    let tag_match = match param("tag_match").as_deref() {
+        Some("any") => TagMatch::Any,
+        Some("all") => TagMatch::All,
+        Some(_) => return Err(ApiError::InvalidParameter("tag_match must be 'any' or 'all'".to_string())),
+        None => TagMatch::default(),
+    };
+    
+    let tz_offset_minutes = match param("tz_offset").map(|value| value.parse::<i64>()) {
+        Some(Ok(offset)) if (-14 * 60..=14 * 60).contains(&offset) => offset,
+        Some(_) => return Err(ApiError::InvalidParameter("tz_offset must be minutes east of UTC between -840 and 840".to_string())),
+        None => 0,
+    };
+    
+    let filter = TodoFilter {
+        completed: parse_bool("completed")?,
+        priority: parse_priority("priority")?,
+        priority_min: parse_priority("priority_min")?,
+        priority_max: parse_priority("priority_max")?,
+        due_after: parse_timestamp("due_after")?,
+        due_before: parse_timestamp("due_before")?,
+        overdue: parse_bool("overdue")?.unwrap_or(false),
+        due_today: parse_bool("due_today")?.unwrap_or(false),
+        tz_offset_minutes,
+        created_after: parse_timestamp("created_after")?,
+        created_before: parse_timestamp("created_before")?,
+        updated_after: parse_timestamp("updated_after")?,
+        updated_before: parse_timestamp("updated_before")?,
+        has_description: parse_bool("has_description")?,
+        tags: normalize_tags(&tags),
+        tag_match,
+    };
+    
+    // Without ?sort= the historical priority order applies (?order= still flips it)
+    let field = match param("sort") {
+        Some(value) => SortField::parse(&value).ok_or_else(|| {
+            ApiError::InvalidParameter("sort must be one of priority, due_date, created_at, updated_at, title".to_string())
+        })?,
+        None => TodoSort::default().field,
+    };
+    let direction = match param("order").as_deref() {
+        Some("asc") => SortDirection::Asc,
+        Some("desc") => SortDirection::Desc,
+        Some(_) => return Err(ApiError::InvalidParameter("order must be 'asc' or 'desc'".to_string())),
+        None => field.default_direction(),
+    };
+    
+    Ok((filter, TodoSort { field, direction }))
+}
+
+// Simple handlers for basic routes
+pub async fn root(_: Request, _: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    Ok(Response::ok("Pali Server API v1.0 - Self-hosted todo management")?)
+}
+
+pub async fn health_check(_: Request, _: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    Ok(Response::ok("OK")?)
 }
 
 // Async handlers for database operations
-pub async fn create_todo(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate API key
-    let _auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => {
-            log_auth_attempt(req.method().to_string().as_str(), req.url()?.path(), Some(&auth.client_name), true);
-            auth
-        },
-        None => {
-            log_auth_attempt(req.method().to_string().as_str(), req.url()?.path(), None, false);
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
+pub async fn create_todo(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let body: CreateTodoPayload = json_body(&mut req).await?;
+    TodoLimits::from_env(&ctx.env).validate_create(&body).map_err(ApiError::Validation)?;
+    
+    let tags = normalize_tags(&body.tags);
+    let todo = caller.db.create_todo(&caller.auth.owner_key_id, body.todo, &tags).await
+        .map_err(internal("Failed to create todo"))?;
+    caller.audit(&req, &ctx, AuditAction::TodoCreated, &todo.todo.id, todo_changes(None, Some(&todo))).await;
+    todo_response(todo)
+}
+
+// Apply several create/update/delete/toggle operations atomically
+pub async fn batch_todos(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let mut body: BatchRequest = json_body(&mut req).await?;
     
-    let body: CreateTodoRequest = match req.json().await {
-        Ok(body) => body,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid JSON body".to_string()))
-                ?.with_status(400));
+    if body.operations.is_empty() || body.operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(ApiError::InvalidParameter(
+            format!("A batch must contain between 1 and {} operations", MAX_BATCH_OPERATIONS)
+        ));
+    }
+    
+    // Every operation is checked up front so an invalid one never starts the batch
+    let limits = TodoLimits::from_env(&ctx.env);
+    let mut errors = Vec::new();
+    for (index, op) in body.operations.iter().enumerate() {
+        let checked = match op {
+            BatchOperation::Create { todo } => limits.validate_create(todo),
+            BatchOperation::Update { changes, .. } => limits.validate_update(changes),
+            BatchOperation::Delete { .. } | BatchOperation::Toggle { .. } => Ok(()),
+        };
+        if let Err(fields) = checked {
+            errors.extend(in_batch(index, fields));
         }
-    };
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+    
+    // The route needs todos:write; deletes also need todos:delete, as DELETE /todos/:id does
+    if body.operations.iter().any(|op| matches!(op, BatchOperation::Delete { .. })) {
+        caller.require(Scope::TodosDelete)?;
+    }
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Database not configured".to_string()))?
-                .with_status(500));
+    for op in &mut body.operations {
+        match op {
+            BatchOperation::Create { todo } => todo.tags = normalize_tags(&todo.tags),
+            BatchOperation::Update { changes, .. } => {
+                changes.tags = changes.tags.as_deref().map(normalize_tags);
+            },
+            BatchOperation::Delete { .. } | BatchOperation::Toggle { .. } => {},
         }
-    };
-
-    let db = Database::new(d1);
+    }
     
-    match db.create_todo(body).await {
-        Ok(todo) => Ok(Response::from_json(&ApiResponse::success(todo))?),
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to create todo: {}", e)))?
-            .with_status(500)),
+    // Targets as they were before the batch, for the audit trail
+    let scope = caller.scope(&req);
+    let mut target_ids: Vec<&str> = body.operations.iter().filter_map(|op| op.target().map(|(id, _)| id)).collect();
+    target_ids.sort_unstable();
+    target_ids.dedup();
+    let before = caller.db.get_todos(&scope, &target_ids).await
+        .map_err(internal("Failed to run batch"))?;
+    
+    let batch = caller.db.execute_batch(&caller.auth.owner_key_id, &scope, body.operations).await
+        .map_err(internal("Failed to run batch"))?;
+    if batch.success {
+        audit_batch(&req, &ctx, &caller, before, &batch).await;
     }
+    
+    // 409 means nothing was written; per-operation statuses say why
+    let status = if batch.success { 200 } else { 409 };
+    Ok(Response::from_json(&batch)?.with_status(status))
 }
 
-pub async fn list_todos(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate API key
-    let _auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<Vec<Todo>>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
+// One event per applied operation, each diffed against the todo as the previous operation left it
+async fn audit_batch(req: &Request, ctx: &RouteContext<RequestInfo>, caller: &Authenticated, before: Vec<TodoResponse>, batch: &BatchResponse) {
+    let audit = AuditContext::new(req, &ctx.data, Some(&caller.auth.key_id));
+    let mut current: HashMap<String, TodoResponse> = before.into_iter().map(|todo| (todo.todo.id.clone(), todo)).collect();
+    
+    let mut events = Vec::new();
+    for result in &batch.results {
+        let Some(id) = result.id.as_deref() else { continue };
+        let previous = current.remove(id);
+        let (action, after) = match result.op.as_str() {
+            "create" => (AuditAction::TodoCreated, result.todo.clone()),
+            "delete" => (AuditAction::TodoDeleted, None),
+            _ => (AuditAction::TodoUpdated, result.todo.clone()),
+        };
+        events.push(audit.event(action, Some(id), Some(todo_changes(previous.as_ref(), after.as_ref()))));
+        if let Some(after) = after {
+            current.insert(id.to_string(), after);
         }
-    };
+    }
+    audit::record_all(&caller.db, &ctx.env, events).await;
+}
+
+pub async fn list_todos(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
     
-    // Parse query parameters manually
     let url = req.url()?;
-    let completed_filter = url.query_pairs()
-        .find(|(key, _)| key == "completed")
-        .and_then(|(_, value)| value.parse::<bool>().ok());
-    
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<Vec<Todo>>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
-
-    let db = Database::new(d1);
+    let (filter, sort) = parse_todo_query(&url)?;
+    let page: PageRequest<TodoCursor> = PageRequest::from_url(&url).map_err(ApiError::InvalidParameter)?;
     
-    match db.list_todos(completed_filter).await {
-        Ok(todos) => Ok(Response::from_json(&ApiResponse::success(todos))?),
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to list todos: {}", e)))?
-            .with_status(500)),
+    if page.after.as_ref().is_some_and(|cursor| !cursor.matches(&sort)) {
+        return Err(ApiError::InvalidParameter("Cursor was issued for a different sort order".to_string()));
     }
+    
+    let todos = caller.db.list_todos(&caller.scope(&req), &filter, &sort, &page).await
+        .map_err(internal("Failed to list todos"))?;
+    Ok(Response::from_json(&PagedResponse::from(todos))?)
 }
 
-pub async fn search_todos(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate API key
-    let _auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<Vec<Todo>>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
+pub async fn search_todos(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
     
     let url = req.url()?;
     let query = match url.query_pairs().find(|(key, _)| key == "q") {
-        Some((_, value)) => value.to_string(),
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Missing 'q' query parameter".to_string()))?
-                .with_status(400));
-        }
+        Some((_, value)) if !value.trim().is_empty() => value.to_string(),
+        Some(_) => return Err(ApiError::InvalidParameter("'q' must not be empty".to_string())),
+        None => return Err(ApiError::MissingParameter("Missing 'q' query parameter".to_string())),
     };
+    let page: PageRequest<SearchCursor> = PageRequest::from_url(&url).map_err(ApiError::InvalidParameter)?;
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<Vec<Todo>>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
+    let todos = caller.db.search_todos(&caller.scope(&req), &query, &page).await
+        .map_err(internal("Failed to search todos"))?;
+    Ok(Response::from_json(&PagedResponse::from(todos))?)
+}
 
-    let db = Database::new(d1);
+pub async fn get_todo(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?;
     
-    match db.search_todos(&query).await {
-        Ok(todos) => Ok(Response::from_json(&ApiResponse::success(todos))?),
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to search todos: {}", e)))?
-            .with_status(500)),
+    match caller.db.get_todo(&caller.scope(&req), id).await.map_err(internal("Failed to get todo"))? {
+        Some(todo) => todo_response(todo),
+        None => Err(ApiError::TodoNotFound),
     }
 }
 
-pub async fn get_todo(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate API key
-    let _auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<Todo>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
+pub async fn update_todo(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?;
+    let expected = parse_if_match(&req)?;
+    let body: UpdateTodoPayload = json_body(&mut req).await?;
+    TodoLimits::from_env(&ctx.env).validate_update(&body).map_err(ApiError::Validation)?;
+    
+    let tags = body.tags.as_deref().map(normalize_tags);
+    let before = caller.db.get_todo(&caller.scope(&req), id).await.map_err(internal("Failed to update todo"))?;
+    let write = caller.db.update_todo(&caller.scope(&req), id, body.todo, tags.as_deref(), &expected).await
+        .map_err(internal("Failed to update todo"))?;
+    let todo = written(write)?;
+    audit_todo_update(&caller, &req, &ctx, before.as_ref(), &todo).await;
+    todo_response(todo)
+}
+
+// JSON Merge Patch (RFC 7396): explicit null clears a field, absent members leave it alone
+pub async fn patch_todo(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?;
+    let expected = parse_if_match(&req)?;
+    let body: serde_json::Value = json_body(&mut req).await?;
+    let patch = TodoPatch::from_merge_patch(&body).map_err(ApiError::Validation)?;
+    TodoLimits::from_env(&ctx.env).validate_patch(&patch).map_err(ApiError::Validation)?;
+    
+    let before = caller.db.get_todo(&caller.scope(&req), id).await.map_err(internal("Failed to update todo"))?;
+    let write = caller.db.patch_todo(&caller.scope(&req), id, &patch, &expected).await
+        .map_err(internal("Failed to update todo"))?;
+    let todo = written(write)?;
+    audit_todo_update(&caller, &req, &ctx, before.as_ref(), &todo).await;
+    todo_response(todo)
+}
+
+pub async fn delete_todo(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?;
+    let expected = parse_if_match(&req)?;
+    
+    let before = caller.db.get_todo(&caller.scope(&req), id).await.map_err(internal("Failed to delete todo"))?;
+    let write = caller.db.delete_todo(&caller.scope(&req), id, &expected).await
+        .map_err(internal("Failed to delete todo"))?;
+    written(write)?;
+    caller.audit(&req, &ctx, AuditAction::TodoDeleted, id, todo_changes(before.as_ref(), None)).await;
+    Ok(Response::from_json(&ApiResponse::success(()))?)
+}
+
+pub async fn toggle_todo(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?;
+    let expected = parse_if_match(&req)?;
+    
+    let before = caller.db.get_todo(&caller.scope(&req), id).await.map_err(internal("Failed to toggle todo"))?;
+    let write = caller.db.toggle_todo(&caller.scope(&req), id, &expected).await
+        .map_err(internal("Failed to toggle todo"))?;
+    let todo = written(write)?;
+    audit_todo_update(&caller, &req, &ctx, before.as_ref(), &todo).await;
+    todo_response(todo)
+}
+
+pub async fn resolve_todo_prefix(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let prefix = param(&ctx, "prefix")?;
     
-    let id = match ctx.param("id") {
-        Some(id) => id,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Missing todo ID".to_string()))?
-                .with_status(400));
-        }
-    };
+    // Validate prefix length (minimum 2 characters to avoid too many matches)
+    if prefix.len() < 2 {
+        return Err(ApiError::InvalidParameter("Prefix must be at least 2 characters long".to_string()));
+    }
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<Todo>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
-
-    let db = Database::new(d1);
+    let matches = caller.db.resolve_id_prefix(&caller.scope(&req), prefix).await
+        .map_err(internal("Failed to resolve prefix"))?;
     
-    match db.get_todo(id).await {
-        Ok(Some(todo)) => Ok(Response::from_json(&ApiResponse::success(todo))?),
-        Ok(None) => Ok(Response::from_json(&ApiResponse::<()>::error("Todo not found".to_string()))?
-            .with_status(404)),
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to get todo: {}", e)))?
-            .with_status(500)),
+    match matches.len() {
+        0 => Err(ApiError::PrefixNotFound(prefix.to_string())),
+        1 => {
+            // Exactly one match - return the full ID
+            let (full_id, _title) = &matches[0];
+            let response = IdResolutionResponse {
+                full_id: full_id.clone(),
+            };
+            Ok(Response::from_json(&ApiResponse::success(response))?)
+        },
+        // Multiple matches - the error's details list the candidates
+        _ => Err(ApiError::AmbiguousPrefix { prefix: prefix.to_string(), candidates: matches }),
     }
 }
 
-pub async fn update_todo(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate API key
-    let _auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<Todo>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
-    
-    let id = match ctx.param("id") {
-        Some(id) => id,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Missing todo ID".to_string()))?
-                .with_status(400));
-        }
-    };
+// Delta sync: todos changed and deleted since the client's last token
+pub async fn sync_todos(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
     
-    let body: UpdateTodoRequest = match req.json().await {
-        Ok(body) => body,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid JSON body".to_string()))?
-                .with_status(400));
-        }
+    // A missing token means a full sync from the beginning
+    let url = req.url()?;
+    let since = match url.query_pairs().find(|(key, _)| key == "since") {
+        Some((_, value)) => match value.parse::<i64>() {
+            Ok(since) if since >= 0 => since,
+            _ => return Err(ApiError::InvalidParameter("Invalid sync token".to_string())),
+        },
+        None => 0,
     };
+    let limit = parse_limit(&url).map_err(ApiError::InvalidParameter)?;
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<Todo>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
+    let changes = caller.db.sync_changes(&caller.scope(&req), since, limit).await
+        .map_err(internal("Failed to sync todos"))?;
+    Ok(Response::from_json(&ApiResponse::success(changes))?)
+}
 
-    let db = Database::new(d1);
+pub async fn list_tags(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
     
-    match db.update_todo(id, body).await {
-        Ok(Some(todo)) => Ok(Response::from_json(&ApiResponse::success(todo))?),
-        Ok(None) => Ok(Response::from_json(&ApiResponse::<()>::error("Todo not found".to_string()))?
-            .with_status(404)),
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to update todo: {}", e)))?
-            .with_status(500)),
-    }
+    let tags = caller.db.list_tags(&caller.scope(&req)).await
+        .map_err(internal("Failed to list tags"))?;
+    Ok(Response::from_json(&ApiResponse::success(tags))?)
 }
 
-pub async fn delete_todo(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate API key
-    let _auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
+// Rename a tag across all of the caller's todos, merging into the target if it already exists
+pub async fn rename_tag(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let body: RenameTagRequest = json_body(&mut req).await?;
     
-    let id = match ctx.param("id") {
-        Some(id) => id,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Missing todo ID".to_string()))?
-                .with_status(400));
-        }
-    };
+    let from = normalize_tag(&body.from);
+    let to = normalize_tag(&body.to);
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
-
-    let db = Database::new(d1);
+    if from.is_empty() || to.is_empty() {
+        return Err(ApiError::InvalidParameter("Both 'from' and 'to' tag names are required".to_string()));
+    }
+    if from == to {
+        return Err(ApiError::InvalidParameter("'from' and 'to' are the same tag".to_string()));
+    }
+    TodoLimits::from_env(&ctx.env).validate_tag("to", &to).map_err(ApiError::Validation)?;
     
-    match db.delete_todo(id).await {
-        Ok(true) => Ok(Response::from_json(&ApiResponse::success(()))?),
-        Ok(false) => Ok(Response::from_json(&ApiResponse::<()>::error("Todo not found".to_string()))?
-            .with_status(404)),
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to delete todo: {}", e)))?
-            .with_status(500)),
+    match caller.db.rename_tag(&caller.scope(&req), &from, &to).await.map_err(internal("Failed to rename tag"))? {
+        Some(tag) => Ok(Response::from_json(&ApiResponse::success(tag))?),
+        None => Err(ApiError::TagNotFound(from)),
     }
 }
 
-pub async fn toggle_todo(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate API key
-    let _auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<Todo>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
+// Admin handlers
+// Any key may rotate itself; rotating another key is covered by POST /admin/keys/:id/rotate
+pub async fn rotate_own_api_key(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = caller.auth.key_id.clone();
+    rotate_api_key(&mut req, &ctx, caller, &id).await
+}
+
+pub async fn rotate_other_api_key(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?.to_string();
+    rotate_api_key(&mut req, &ctx, caller, &id).await
+}
+
+// Issue a successor for the key; the old key keeps working for the grace period, then is deactivated
+async fn rotate_api_key(req: &mut Request, ctx: &RouteContext<RequestInfo>, caller: Authenticated, id: &str) -> ApiResult<Response> {
+    let RotateApiKeyPayload { grace_period_secs } = optional_json_body(req).await?;
+    let grace_period_secs = match grace_period_secs {
+        Some(secs) if !(0..=MAX_ROTATION_GRACE_SECS).contains(&secs) => {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                "grace_period_secs",
+                "validation.out_of_range",
+                &format!("must be between 0 and {}", MAX_ROTATION_GRACE_SECS),
+            )]));
+        },
+        Some(secs) => secs,
+        None => rotation_grace_secs(&ctx.env),
     };
     
-    let id = match ctx.param("id") {
-        Some(id) => id,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Missing todo ID".to_string()))?
-                .with_status(400));
-        }
-    };
+    let key = caller.db.get_api_key(id).await
+        .map_err(internal("Failed to get API key"))?
+        .ok_or(ApiError::KeyNotFound)?;
+    // The successor inherits the key's scopes, and a key can only hand out what it holds itself
+    for scope in &key.scopes {
+        caller.require(*scope)?;
+    }
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<Todo>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
+    let (api_key, lookup_id) = generate_api_key();
+    let key_hash = hash_api_key(&api_key);
+    
+    let now = chrono::Utc::now().timestamp();
+    let (successor_id, previous_key_expires_at) = caller.db.rotate_api_key(id, key_hash, lookup_id, now + grace_period_secs).await
+        .map_err(internal("Failed to rotate API key"))?
+        .ok_or(ApiError::KeyNotRotatable)?;
+    invalidate_cached_key(id);
+    
+    // Recorded against both keys, so either id finds the rotation in the audit log
+    let audit = AuditContext::new(req, &ctx.data, Some(&caller.auth.key_id));
+    audit::record_all(&caller.db, &ctx.env, vec![
+        audit.event(AuditAction::KeyRotated, Some(id), Some(json!({
+            "replaced_by": { "from": null, "to": successor_id },
+            "expires_at": { "from": key.expires_at, "to": previous_key_expires_at },
+        }))),
+        audit.event(AuditAction::KeyRotated, Some(&successor_id), Some(json!({
+            "rotated_from": { "from": null, "to": id },
+        }))),
+    ]).await;
+    
+    let response = RotatedApiKey {
+        key: CreatedApiKey {
+            key: ApiKeyResponse {
+                id: successor_id,
+                client_name: key.client_name,
+                key_type: key.key_type,
+                api_key,
+                created_at: now,
+            },
+            scopes: key.scopes,
+            rate_limit_per_minute: key.rate_limit_per_minute,
+            expires_at: key.expires_at,
+        },
+        rotated_from: id.to_string(),
+        previous_key_expires_at,
     };
+    Ok(Response::from_json(&ApiResponse::success(response))?)
+}
 
-    let db = Database::new(d1);
+// Parse the scopes requested for a new key, reporting unknown names and scopes the key type cannot hold
+fn requested_scopes(key_type: &KeyType, names: &[String], errors: &mut Vec<FieldError>) -> Vec<Scope> {
+    if names.iter().any(|name| Scope::parse(name).is_none()) {
+        let known: Vec<&str> = Scope::ALL.iter().map(|scope| scope.as_str()).collect();
+        errors.push(FieldError::new("scopes", "validation.unknown_scope", &format!("must only contain {}", known.join(", "))));
+    } else if names.is_empty() {
+        errors.push(FieldError::new("scopes", "validation.required", "must contain at least one scope"));
+    }
     
-    match db.toggle_todo(id).await {
-        Ok(Some(todo)) => Ok(Response::from_json(&ApiResponse::success(todo))?),
-        Ok(None) => Ok(Response::from_json(&ApiResponse::<()>::error("Todo not found".to_string()))?
-            .with_status(404)),
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to toggle todo: {}", e)))?
-            .with_status(500)),
+    let scopes = parse_scopes(&names.join(" "));
+    if *key_type == KeyType::Client && scopes.iter().any(|scope| scope.admin_only()) {
+        errors.push(FieldError::new("scopes", "validation.admin_scope", "admin-only scopes require key_type Admin"));
     }
+    scopes
 }
 
-pub async fn resolve_todo_prefix(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate API key
-    let _auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => {
-            log_auth_attempt(req.method().to_string().as_str(), req.url()?.path(), Some(&auth.client_name), true);
-            auth
+// Resolve an expiry to a Unix timestamp (None = never), which must lie in the future
+fn requested_expiry(expiry: &ApiKeyExpiry, now: i64, errors: &mut Vec<FieldError>) -> Option<i64> {
+    match (expiry.expires_at, expiry.expires_in) {
+        (Some(_), Some(_)) => {
+            errors.push(FieldError::new("expires_in", "validation.conflicting_fields", "cannot be combined with expires_at"));
+            None
         },
-        None => {
-            log_auth_attempt(req.method().to_string().as_str(), req.url()?.path(), None, false);
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
+        (Some(expires_at), None) if expires_at <= now => {
+            errors.push(FieldError::new("expires_at", "validation.out_of_range", "must be in the future"));
+            None
+        },
+        (None, Some(expires_in)) if expires_in <= 0 => {
+            errors.push(FieldError::new("expires_in", "validation.out_of_range", "must be at least 1 second"));
+            None
+        },
+        (expires_at, expires_in) => expires_at.or_else(|| expires_in.map(|secs| now.saturating_add(secs))),
+    }
+}
+
+pub async fn create_api_key(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let CreateApiKeyPayload { key, scopes: requested, rate_limit_per_minute, expiry } = json_body(&mut req).await?;
+    let now = chrono::Utc::now().timestamp();
+    
+    let mut errors = Vec::new();
+    let expires_at = requested_expiry(&expiry, now, &mut errors);
+    let scopes = match &requested {
+        Some(names) => requested_scopes(&key.key_type, names, &mut errors),
+        None => Scope::preset(&key.key_type),
+    };
+    if rate_limit_per_minute == Some(0) {
+        errors.push(FieldError::new(
+            "rate_limit_per_minute",
+            "validation.out_of_range",
+            "must be at least 1 request per minute",
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
     
-    let prefix = match ctx.param("prefix") {
-        Some(prefix) => prefix,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Missing prefix parameter".to_string()))?
-                .with_status(400));
-        }
+    // A key can only hand out what it holds itself
+    for scope in &scopes {
+        caller.require(*scope)?;
+    }
+    
+    let (api_key, lookup_id) = generate_api_key();
+    let key_hash = hash_api_key(&api_key);
+    
+    // Keys created without explicit scopes stay on their type's preset
+    let options = ApiKeyOptions {
+        scopes: requested.is_some().then(|| scopes.clone()),
+        rate_limit_per_minute,
+        expires_at,
+    };
+    let id = caller.db.create_api_key(key_hash, lookup_id, key.client_name.clone(), key.key_type.clone(), &options).await
+        .map_err(internal("Failed to create API key"))?;
+    let created = json!({
+        "client_name": key.client_name,
+        "key_type": key.key_type,
+        "scopes": scopes,
+        "rate_limit_per_minute": rate_limit_per_minute,
+        "expires_at": expires_at,
+    });
+    let changes = audit::changes(None, Some(&created), &["client_name", "key_type", "scopes", "rate_limit_per_minute", "expires_at"]);
+    caller.audit(&req, &ctx, AuditAction::KeyCreated, &id, changes).await;
+    let response = CreatedApiKey {
+        key: ApiKeyResponse {
+            id,
+            client_name: key.client_name,
+            key_type: key.key_type,
+            api_key,
+            created_at: chrono::Utc::now().timestamp(),
+        },
+        scopes,
+        rate_limit_per_minute,
+        expires_at,
     };
+    Ok(Response::from_json(&ApiResponse::success(response))?)
+}
+
+pub async fn list_api_keys(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let Authenticated { db, .. } = Authenticated::from_request(&req, &ctx).await?;
+    let page = PageRequest::from_url(&req.url()?).map_err(ApiError::InvalidParameter)?;
     
-    // Validate prefix length (minimum 2 characters to avoid too many matches)
-    if prefix.len() < 2 {
-        return Ok(Response::from_json(&ApiResponse::<()>::error("Prefix must be at least 2 characters long".to_string()))?
-            .with_status(400));
+    let keys = db.list_api_keys(&page).await.map_err(internal("Failed to list API keys"))?;
+    let key_details = keys.map(ApiKeyDetails::from);
+    Ok(Response::from_json(&PagedResponse::from(key_details))?)
+}
+
+// Audit trail, newest first, filtered by any of ?event_type=, ?actor_key_id=, ?target_id=,
+// ?client_ip=, ?created_after= and ?created_before=
+pub async fn list_audit_events(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let Authenticated { db, .. } = Authenticated::from_request(&req, &ctx).await?;
+    
+    let url = req.url()?;
+    let param = |name: &str| url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string());
+    let parse_timestamp = |name: &str| -> ApiResult<Option<i64>> {
+        param(name)
+            .map(|value| value.parse::<i64>().map_err(|_| ApiError::InvalidParameter(format!("{} must be a Unix timestamp", name))))
+            .transpose()
+    };
+    
+    let event_type = param("event_type");
+    if event_type.as_deref().is_some_and(|value| AuditAction::parse(value).is_none()) {
+        let known: Vec<&str> = AuditAction::ALL.iter().map(|action| action.as_str()).collect();
+        return Err(ApiError::InvalidParameter(format!("event_type must be one of {}", known.join(", "))));
     }
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<IdResolutionResponse>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
+    let filter = AuditFilter {
+        event_type,
+        actor_key_id: param("actor_key_id"),
+        target_id: param("target_id"),
+        client_ip: param("client_ip"),
+        created_after: parse_timestamp("created_after")?,
+        created_before: parse_timestamp("created_before")?,
     };
+    let page: PageRequest<AuditCursor> = PageRequest::from_url(&url).map_err(ApiError::InvalidParameter)?;
+    
+    let events = db.list_audit_events(&filter, &page).await.map_err(internal("Failed to list audit events"))?;
+    Ok(Response::from_json(&PagedResponse::from(events))?)
+}
 
-    let db = Database::new(d1);
-    
-    match db.resolve_id_prefix(prefix).await {
-        Ok(matches) => {
-            match matches.len() {
-                0 => {
-                    // No matches found
-                    Ok(Response::from_json(&ApiResponse::<()>::error(format!("No todo found with prefix '{}'", prefix)))?
-                        .with_status(404))
-                },
-                1 => {
-                    // Exactly one match - return the full ID
-                    let (full_id, _title) = &matches[0];
-                    let response = IdResolutionResponse {
-                        full_id: full_id.clone(),
-                    };
-                    Ok(Response::from_json(&ApiResponse::success(response))?)
-                },
-                _ => {
-                    // Multiple matches - return ambiguous error with details
-                    let match_details: Vec<String> = matches.iter()
-                        .take(3) // Limit to first 3 matches for readability
-                        .map(|(id, title)| {
-                            let short_id = &id[..std::cmp::min(8, id.len())]; // Show first 8 chars
-                            format!("{} ({})", short_id, title)
-                        })
-                        .collect();
-                    
-                    let error_msg = format!(
-                        "Ambiguous prefix '{}' matches {} todos: {}{}",
-                        prefix,
-                        matches.len(),
-                        match_details.join(", "),
-                        if matches.len() > 3 { ", ..." } else { "" }
-                    );
-                    
-                    Ok(Response::from_json(&ApiResponse::<()>::error(error_msg))?
-                        .with_status(409)) // Conflict status for ambiguous matches
-                }
-            }
-        },
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to resolve prefix: {}", e)))?
-            .with_status(500)),
-    }
+// Current and expected schema versions plus the migrations still pending
+pub async fn get_schema_status(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let Authenticated { db, .. } = Authenticated::from_request(&req, &ctx).await?;
+    
+    let status = schema::status(&db).await.map_err(internal("Failed to read schema version"))?;
+    Ok(Response::from_json(&ApiResponse::success(status))?)
 }
 
-// Admin handlers
-pub async fn rotate_admin_key(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    // Admin authentication check deprecated - endpoint replaced with /reinitialize
+// Apply pending migrations from the set compiled into this build
+pub async fn migrate_schema(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let Authenticated { db, .. } = Authenticated::from_request(&req, &ctx).await?;
+    
+    let applied = schema::migrate(&db).await.map_err(internal("Failed to migrate schema"))?;
+    console_log!("SCHEMA: applied {} migration(s): {:?}", applied.len(), applied);
     
-    Ok(Response::from_json(&ApiResponse::<()>::error("Use POST /reinitialize for admin key rotation".to_string()))?
-        .with_status(410)) // Gone - use new endpoint
+    let status = schema::status(&db).await.map_err(internal("Failed to read schema version"))?;
+    Ok(Response::from_json(&ApiResponse::success(status))?)
 }
 
-pub async fn create_api_key(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate admin API key
-    let auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
+// Extend, shorten or remove a key's expiry; a body without expires_at or expires_in removes it
+pub async fn set_api_key_expiry(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?;
+    let expiry: ApiKeyExpiry = json_body(&mut req).await?;
     
-    if !is_admin(&auth) {
-        return Ok(Response::from_json(&ApiResponse::<()>::error("Admin privileges required".to_string()))?
-            .with_status(403));
+    let mut errors = Vec::new();
+    let expires_at = requested_expiry(&expiry, chrono::Utc::now().timestamp(), &mut errors);
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
     }
     
-    let body: CreateApiKeyRequest = match req.json().await {
-        Ok(body) => body,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid JSON body".to_string()))?
-                .with_status(400));
+    let mut key = caller.db.get_api_key(id).await
+        .map_err(internal("Failed to get API key"))?
+        .ok_or(ApiError::KeyNotFound)?;
+    // A replaced key stays retired; its successor carries the lifetime now
+    if let Some(replaced_by) = key.replaced_by.clone() {
+        return Err(ApiError::KeyReplaced { replaced_by });
+    }
+    // Extending a key's lifetime keeps its scopes usable, so the caller must hold them
+    for scope in &key.scopes {
+        caller.require(*scope)?;
+    }
+    if !caller.db.set_api_key_expiry(id, expires_at).await.map_err(internal("Failed to update API key"))? {
+        // Only shortening an active admin key's expiry can be refused; otherwise the key was purged
+        if key.key_type == KeyType::Admin && key.active {
+            return Err(ApiError::LastAdminKey);
         }
-    };
+        return Err(ApiError::KeyNotFound);
+    }
+    // A shortened expiry must take effect here now, not when the cached entry runs out
+    invalidate_cached_key(id);
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<ApiKeyResponse>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
+    if key.expires_at != expires_at {
+        let changes = json!({ "expires_at": { "from": key.expires_at, "to": expires_at } });
+        caller.audit(&req, &ctx, AuditAction::KeyUpdated, id, changes).await;
+    }
+    key.expires_at = expires_at;
+    Ok(Response::from_json(&ApiResponse::success(ApiKeyDetails::from(key)))?)
+}
 
-    let db = Database::new(d1);
-    let api_key = generate_api_key();
-    let key_hash = hash_api_key(&api_key);
+// One key by id, revoked and expired ones included
+pub async fn get_api_key(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let Authenticated { db, .. } = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?;
     
-    match db.create_api_key(key_hash, body.client_name.clone(), body.key_type.clone()).await {
-        Ok(id) => {
-            let response = ApiKeyResponse {
-                id,
-                client_name: body.client_name,
-                key_type: body.key_type,
-                api_key,
-                created_at: chrono::Utc::now().timestamp(),
-            };
-            Ok(Response::from_json(&ApiResponse::success(response))?)
-        },
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to create API key: {}", e)))?
-            .with_status(500)),
-    }
+    let key = db.get_api_key(id).await
+        .map_err(internal("Failed to get API key"))?
+        .ok_or(ApiError::KeyNotFound)?;
+    Ok(Response::from_json(&ApiResponse::success(ApiKeyDetails::from(key)))?)
 }
 
-pub async fn list_api_keys(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate admin API key
-    let auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
+// Rename a key and/or revoke ("active": false) or reactivate ("active": true) it
+pub async fn update_api_key(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?.to_string();
+    let UpdateApiKeyPayload { client_name, active } = json_body(&mut req).await?;
     
-    if !is_admin(&auth) {
-        return Ok(Response::from_json(&ApiResponse::<()>::error("Admin privileges required".to_string()))?
-            .with_status(403));
+    let client_name = client_name.map(|name| name.trim().to_string());
+    if client_name.as_deref() == Some("") {
+        return Err(ApiError::Validation(vec![FieldError::new("client_name", "validation.required", "must not be empty")]));
     }
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<Vec<ApiKeyInfo>>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
-
-    let db = Database::new(d1);
-    
-    match db.list_api_keys().await {
-        Ok(keys) => {
-            let key_infos: Vec<ApiKeyInfo> = keys.into_iter().map(|k| ApiKeyInfo {
-                id: k.id,
-                client_name: k.client_name,
-                key_type: k.key_type,
-                last_used: k.last_used,
-                created_at: k.created_at,
-                active: k.active,
-            }).collect();
-            Ok(Response::from_json(&ApiResponse::success(key_infos))?)
+    let key = caller.db.get_api_key(&id).await
+        .map_err(internal("Failed to get API key"))?
+        .ok_or(ApiError::KeyNotFound)?;
+    
+    // The active flag first: it is the change that can be refused
+    match active {
+        Some(false) if key.active => revoke(&caller, &req, &ctx, &key).await?,
+        Some(true) if !key.active => {
+            if let Some(replaced_by) = key.replaced_by.clone() {
+                return Err(ApiError::KeyReplaced { replaced_by });
+            }
+            // Bringing a key back hands out its scopes again, so the caller must hold them
+            for scope in &key.scopes {
+                caller.require(*scope)?;
+            }
+            caller.db.reactivate_api_key(&id).await.map_err(internal("Failed to reactivate API key"))?;
+            caller.audit(&req, &ctx, AuditAction::KeyUpdated, &id, json!({ "active": { "from": false, "to": true } })).await;
         },
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to list API keys: {}", e)))?
-            .with_status(500)),
+        _ => {},
     }
+    
+    if let Some(client_name) = client_name.filter(|name| *name != key.client_name) {
+        caller.db.rename_api_key(&id, &client_name).await.map_err(internal("Failed to rename API key"))?;
+        invalidate_cached_key(&id);
+        let changes = json!({ "client_name": { "from": key.client_name, "to": client_name } });
+        caller.audit(&req, &ctx, AuditAction::KeyUpdated, &id, changes).await;
+    }
+    
+    let key = caller.db.get_api_key(&id).await
+        .map_err(internal("Failed to get API key"))?
+        .ok_or(ApiError::KeyNotFound)?;
+    Ok(Response::from_json(&ApiResponse::success(ApiKeyDetails::from(key)))?)
 }
 
-pub async fn revoke_api_key(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Validate admin API key
-    let auth = match validate_api_key_from_request(&req, &ctx.env).await {
-        Some(auth) => auth,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Invalid or missing API key".to_string()))?
-                .with_status(401));
-        }
-    };
+// Revoking an already revoked key changes nothing and succeeds
+pub async fn revoke_api_key(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let id = param(&ctx, "id")?;
     
-    if !is_admin(&auth) {
-        return Ok(Response::from_json(&ApiResponse::<()>::error("Admin privileges required".to_string()))?
-            .with_status(403));
+    let key = caller.db.get_api_key(id).await
+        .map_err(internal("Failed to get API key"))?
+        .ok_or(ApiError::KeyNotFound)?;
+    if key.active {
+        revoke(&caller, &req, &ctx, &key).await?;
     }
+    Ok(Response::from_json(&ApiResponse::success(()))?)
+}
+
+// Shared by DELETE /admin/keys/:id and PATCH with "active": false
+async fn revoke(caller: &Authenticated, req: &Request, ctx: &RouteContext<RequestInfo>, key: &ApiKey) -> ApiResult<()> {
+    let revoked = caller.db.revoke_api_key(&key.id).await.map_err(internal("Failed to revoke API key"))?;
+    // Only admin keys can be refused; otherwise another request revoked the key first
+    if !revoked && key.key_type == KeyType::Admin {
+        return Err(ApiError::LastAdminKey);
+    }
+    invalidate_cached_key(&key.id);
+    caller.audit(req, ctx, AuditAction::KeyRevoked, &key.id, json!({ "active": { "from": true, "to": false } })).await;
+    Ok(())
+}
+
+// Hard-delete keys revoked before revoked_before (default: 30 days ago); see Database::purge_api_keys
+pub async fn purge_api_keys(mut req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let caller = Authenticated::from_request(&req, &ctx).await?;
+    let PurgeApiKeysPayload { revoked_before } = optional_json_body(&mut req).await?;
+    let revoked_before = revoked_before.unwrap_or_else(|| chrono::Utc::now().timestamp() - DEFAULT_PURGE_AFTER_SECS);
     
-    let id = match ctx.param("id") {
-        Some(id) => id,
-        None => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Missing key ID".to_string()))?
-                .with_status(400));
-        }
-    };
+    let (purged, retained) = caller.db.purge_api_keys(revoked_before).await
+        .map_err(internal("Failed to purge API keys"))?;
     
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
-
-    let db = Database::new(d1);
+    let audit = AuditContext::new(&req, &ctx.data, Some(&caller.auth.key_id));
+    let events = purged.iter().map(|id| audit.event(AuditAction::KeyPurged, Some(id), None)).collect();
+    audit::record_all(&caller.db, &ctx.env, events).await;
     
-    match db.revoke_api_key(id).await {
-        Ok(_) => Ok(Response::from_json(&ApiResponse::success(()))?),
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to revoke API key: {}", e)))?
-            .with_status(500)),
-    }
+    Ok(Response::from_json(&ApiResponse::success(PurgeApiKeysResponse { purged, retained }))?)
 }
 
 // One-time initialization endpoint - creates the first admin key
-pub async fn initialize_server(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
-
-    let db = Database::new(d1);
+pub async fn initialize_server(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let db = database(&ctx.env)?;
+    rate_limit::for_setup(&db, &ctx.env, &ctx.data, &client_ip(&req)).await?;
     
-    // Check if already initialized
-    match db.is_initialized().await {
-        Ok(true) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Server already initialized".to_string()))?
-                .with_status(409)); // Conflict
-        },
-        Ok(false) => {
-            // Proceed with initialization
-        },
-        Err(e) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to check initialization status: {}", e)))?
-                .with_status(500));
-        }
+    // A brand-new database has no tables yet; create the schema before the first admin key
+    if schema::current_version(&db).await.map_err(internal("Failed to read schema version"))? == 0 {
+        schema::migrate(&db).await.map_err(internal("Failed to create database schema"))?;
+    }
+    
+    if db.is_initialized().await.map_err(internal("Failed to check initialization status"))? {
+        return Err(ApiError::AlreadyInitialized);
     }
     
     // Generate the first admin key
-    let api_key = generate_api_key();
+    let (api_key, lookup_id) = generate_api_key();
     let key_hash = hash_api_key(&api_key);
     
-    match db.initialize_with_admin_key(key_hash).await {
-        Ok(id) => {
-            let response = ApiKeyResponse {
-                id,
-                client_name: "Initial Admin Key".to_string(),
-                key_type: KeyType::Admin,
-                api_key,
-                created_at: chrono::Utc::now().timestamp(),
-            };
-            Ok(Response::from_json(&ApiResponse::success(response))?)
-        },
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to initialize server: {}", e)))?
-            .with_status(500)),
-    }
+    let id = db.initialize_with_admin_key(key_hash, lookup_id).await
+        .map_err(internal("Failed to initialize server"))?;
+    AuditContext::new(&req, &ctx.data, None)
+        .record(&db, &ctx.env, AuditAction::ServerInitialized, Some(&id), None).await;
+    let response = ApiKeyResponse {
+        id,
+        client_name: "Initial Admin Key".to_string(),
+        key_type: KeyType::Admin,
+        api_key,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    Ok(Response::from_json(&ApiResponse::success(response))?)
 }
 
 // Emergency reinitialize endpoint - deactivates ALL admin keys and creates new one
-pub async fn reinitialize_server(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let d1 = match ctx.env.d1("DB") {
-        Ok(db) => db,
-        Err(_) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Database not configured".to_string()))?
-                .with_status(500));
-        }
-    };
-
-    let db = Database::new(d1);
+// Requires the RECOVERY_SECRET worker secret in the X-Recovery-Secret header
+pub async fn reinitialize_server(req: Request, ctx: RouteContext<RequestInfo>) -> ApiResult<Response> {
+    let db = database(&ctx.env)?;
+    let ip = client_ip(&req);
+    let path = req.path();
+    rate_limit::for_setup(&db, &ctx.env, &ctx.data, &ip).await?;
+    
+    // Throttle repeated failures from the same client before looking at the secret
+    let window_start = chrono::Utc::now().timestamp() - RECOVERY_THROTTLE_WINDOW_SECS;
+    let failures = db.count_failed_recovery_attempts(&ip, window_start).await
+        .map_err(internal("Failed to check recovery attempts"))?;
+    if failures >= MAX_FAILED_RECOVERY_ATTEMPTS {
+        console_log!("RECOVERY THROTTLED: POST {} - ip: {} ({} recent failures)", path, ip, failures);
+        return Err(ApiError::RecoveryThrottled);
+    }
     
-    // Check if database is initialized (has any admin keys)
-    match db.is_initialized().await {
-        Ok(false) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error("Server not initialized. Use POST /initialize first".to_string()))?
-                .with_status(400));
+    match verify_recovery_secret(&req, &ctx.env) {
+        RecoveryCheck::Valid => {
+            console_log!("RECOVERY SUCCESS: POST {} - ip: {}", path, ip);
+            db.record_recovery_attempt(&ip, true).await?;
         },
-        Ok(true) => {
-            // Proceed with reinitialization
+        RecoveryCheck::Invalid => {
+            console_log!("RECOVERY FAILED: POST {} - ip: {}", path, ip);
+            db.record_recovery_attempt(&ip, false).await?;
+            AuditContext::new(&req, &ctx.data, None)
+                .record(&db, &ctx.env, AuditAction::AuthFailed, None, None).await;
+            return Err(ApiError::InvalidRecoverySecret);
         },
-        Err(e) => {
-            return Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to check initialization status: {}", e)))?
-                .with_status(500));
+        RecoveryCheck::NotConfigured => {
+            console_log!("RECOVERY DISABLED: POST {} - ip: {} (RECOVERY_SECRET not set)", path, ip);
+            return Err(ApiError::RecoveryDisabled);
         }
     }
     
+    // Check if database is initialized (has any admin keys)
+    if !db.is_initialized().await.map_err(internal("Failed to check initialization status"))? {
+        return Err(ApiError::NotInitialized);
+    }
+    
     // Generate new admin key
-    let api_key = generate_api_key();
+    let (api_key, lookup_id) = generate_api_key();
     let key_hash = hash_api_key(&api_key);
     
-    match db.reinitialize_admin_keys(key_hash).await {
-        Ok(id) => {
-            let response = ApiKeyResponse {
-                id,
-                client_name: "Reinitialized Admin Key".to_string(),
-                key_type: KeyType::Admin,
-                api_key,
-                created_at: chrono::Utc::now().timestamp(),
-            };
-            Ok(Response::from_json(&ApiResponse::success(response))?)
-        },
-        Err(e) => Ok(Response::from_json(&ApiResponse::<()>::error(format!("Failed to reinitialize server: {}", e)))?
-            .with_status(500)),
-    }
-}
\ No newline at end of file
+    let id = db.reinitialize_admin_keys(key_hash, lookup_id).await
+        .map_err(internal("Failed to reinitialize server"))?;
+    invalidate_cached_admin_keys();
+    AuditContext::new(&req, &ctx.data, None)
+        .record(&db, &ctx.env, AuditAction::ServerReinitialized, Some(&id), None).await;
+    let response = ApiKeyResponse {
+        id,
+        client_name: "Reinitialized Admin Key".to_string(),
+        key_type: KeyType::Admin,
+        api_key,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    Ok(Response::from_json(&ApiResponse::success(response))?)
+}
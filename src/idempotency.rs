@@ -0,0 +1,149 @@
+// Idempotency-Key support for mutating routes
+// A retried request carrying the same key replays the first response instead of running again.
+// Keys are scoped to the calling API key and remembered for IDEMPOTENCY_WINDOW_SECS.
+
+use std::future::Future;
+#[allow(clippy::wildcard_imports)]
+use crate::platform::*;
+use sha2::{Digest, Sha256};
+use chrono::Utc;
+use crate::db::Database;
+use crate::auth::validate_api_key_from_request;
+use crate::error::{ApiError, ApiResult};
+use crate::middleware::{api, database, require_scope, RequestInfo};
+use crate::rate_limit;
+
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+// Optional [vars] entry overriding how long keys are remembered
+const WINDOW_VAR: &str = "IDEMPOTENCY_WINDOW_SECS";
+const DEFAULT_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+// An in-flight claim older than this is assumed abandoned (e.g. the isolate died mid-request)
+const STALE_CLAIM_SECS: i64 = 60;
+
+const MAX_KEY_LENGTH: usize = 255;
+
+fn window_secs(env: &Env) -> i64 {
+    env.var(WINDOW_VAR)
+        .ok()
+        .and_then(|value| value.to_string().parse::<i64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_WINDOW_SECS)
+}
+
+// Fingerprint of what the client asked for; reusing a key for a different request is an error
+fn request_hash(method: &str, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+fn replay(status_code: u16, headers: Option<String>, body: Option<String>) -> Result<Response> {
+    let mut response = Response::ok(body.unwrap_or_default())?.with_status(status_code);
+
+    let headers: Vec<(String, String)> = headers
+        .and_then(|headers| serde_json::from_str(&headers).ok())
+        .unwrap_or_default();
+    for (name, value) in headers {
+        response.headers_mut().set(&name, &value)?;
+    }
+    response.headers_mut().set("Idempotency-Replayed", "true")?;
+    Ok(response)
+}
+
+// Route adapter: the standard request pipeline with Idempotency-Key handling around the handler
+pub async fn idempotent<F, Fut>(req: Request, ctx: RouteContext<RequestInfo>, handler: F) -> Result<Response>
+where
+    F: FnOnce(Request, RouteContext<RequestInfo>) -> Fut,
+    Fut: Future<Output = ApiResult<Response>>,
+{
+    api(req, ctx, |req, ctx| with_idempotency(req, ctx, handler)).await
+}
+
+// Requests without the header, or without a valid API key, go straight to the handler
+async fn with_idempotency<F, Fut>(req: Request, ctx: RouteContext<RequestInfo>, handler: F) -> ApiResult<Response>
+where
+    F: FnOnce(Request, RouteContext<RequestInfo>) -> Fut,
+    Fut: Future<Output = ApiResult<Response>>,
+{
+    let key = match req.headers().get(IDEMPOTENCY_HEADER)? {
+        Some(key) => key,
+        None => return handler(req, ctx).await,
+    };
+
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(ApiError::InvalidIdempotencyKey(
+            format!("{} must be 1-{} characters", IDEMPOTENCY_HEADER, MAX_KEY_LENGTH)
+        ));
+    }
+
+    let auth = match validate_api_key_from_request(&req, &ctx.env).await {
+        Some(auth) => auth,
+        None => return handler(req, ctx).await,
+    };
+
+    let db = database(&ctx.env)?;
+    let request_id = ctx.data.request_id.clone();
+
+    // Replays spend from the key's budget too; the handler will not charge this request again
+    rate_limit::for_key(&db, &ctx.env, &ctx.data, &auth).await?;
+    // and are only served to keys still allowed to make the request
+    if let Some(scope) = ctx.data.required_scope {
+        require_scope(&auth, scope)?;
+    }
+
+    let body = req.clone()?.bytes().await?;
+    let hash = request_hash(req.method().as_ref(), req.url()?.path(), &body);
+
+    let now = Utc::now().timestamp();
+    let claimed = db.claim_idempotency_key(
+        &auth.key_id,
+        &key,
+        &hash,
+        now - window_secs(&ctx.env),
+        now - STALE_CLAIM_SECS,
+    ).await?;
+
+    if !claimed {
+        return match db.find_idempotency_record(&auth.key_id, &key).await? {
+            Some(record) if record.request_hash != hash => Err(ApiError::IdempotencyKeyReused),
+            Some(record) => match record.status_code {
+                Some(status_code) => Ok(replay(status_code, record.response_headers, record.response_body)?),
+                None => Err(ApiError::IdempotencyInProgress),
+            },
+            // The record vanished between the two queries; run without caching rather than fail
+            None => handler(req, ctx).await,
+        };
+    }
+
+    match handler(req, ctx).await {
+        Ok(mut response) if response.status_code() < 500 => {
+            remember(&db, &auth.key_id, &key, &mut response).await?;
+            Ok(response)
+        },
+        // Client errors are remembered as the response the pipeline renders from them
+        Err(e) if e.status() < 500 => {
+            remember(&db, &auth.key_id, &key, &mut e.to_response(&request_id)?).await?;
+            Err(e)
+        },
+        // Server errors are not remembered, so a retry can still succeed
+        result => {
+            db.release_idempotency_key(&auth.key_id, &key).await?;
+            result
+        }
+    }
+}
+
+// Store the finished response for replay
+async fn remember(db: &Database, key_id: &str, key: &str, response: &mut Response) -> ApiResult<()> {
+    let body = response.cloned()?.text().await?;
+    let headers: Vec<(String, String)> = response.headers().entries().collect();
+    let headers = serde_json::to_string(&headers).map_err(Error::from)?;
+    db.complete_idempotency_key(key_id, key, response.status_code(), &headers, &body).await?;
+    Ok(())
+}
@@ -1,37 +1,286 @@
 // Core modules for the Pali todo server
-mod models;     // Data structures and API types
-mod db;         // Database operations and D1 integration
-mod auth;       // API key authentication middleware
-mod handlers;   // HTTP endpoint handlers
+pub mod models;     // Data structures and API types
+pub mod db;         // Database operations over a pluggable storage backend
+mod auth;           // API key authentication middleware
+mod handlers;       // HTTP endpoint handlers
+mod error;          // Typed API errors with status codes and machine-readable codes
+mod middleware;     // Request pipeline: auth extraction, role guards, error mapping
+mod validation;     // Configurable limits for todo payloads
+pub mod pagination; // Cursor-based pagination helpers
+mod idempotency;    // Idempotency-Key replay for mutating routes
+mod rate_limit;     // Token bucket request budgets per key and per client IP
+mod audit;          // Audit trail of security and data events
+pub mod schema;     // Embedded migrations and schema version checks
+pub mod storage;    // D1 and native SQLite backends for Database
+#[cfg(feature = "server")]
+pub mod native;     // Worker API stand-ins for the self-hosted server
 
-use worker::*;
+// Request/Response/Router/Env come from worker on Cloudflare and from native.rs when self-hosted
+#[cfg(not(feature = "server"))]
+use worker as platform;
+#[cfg(feature = "server")]
+use native as platform;
 
+#[allow(clippy::wildcard_imports)]
+use platform::*;
+use idempotency::idempotent;
+use middleware::{api, requires, RequestInfo, REQUEST_ID_HEADER};
+use models::Scope;
+
+#[cfg(not(feature = "server"))]
 #[event(fetch)]
 async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
+    handle(req, env).await
+}
+
+// Serve one request; the Workers entry point and the self-hosted server share every route
+pub async fn handle(req: Request, env: Env) -> Result<Response> {
+    let info = RequestInfo::generate();
     
-    // Workers router - much cleaner for edge computing
-    Router::new()
-        .get("/", handlers::root)
-        .get("/health", handlers::health_check)
+    // Refuse to serve against a database that is behind this build's migrations
+    if let Err(e) = schema::schema_guard(&req, &env).await {
+        let mut response = e.to_response(&info.request_id)?;
+        response.headers_mut().set(REQUEST_ID_HEADER, &info.request_id)?;
+        return Ok(response);
+    }
+    
+    // Same router on both platforms (worker's on Cloudflare, native.rs when self-hosted)
+    // Every handler runs inside middleware::api; idempotent() adds Idempotency-Key replay on top
+    // requires() names the API key scope a route needs (see models::Scope)
+    Router::with_data(info)
+        .get_async("/", |req, ctx| api(req, ctx, handlers::root))
+        .get_async("/health", |req, ctx| api(req, ctx, handlers::health_check))
         // One-time initialization endpoint
-        .post_async("/initialize", handlers::initialize_server)
+        .post_async("/initialize", |req, ctx| api(req, ctx, handlers::initialize_server))
         // Emergency reinitialize endpoint (admin key rotation)
-        .post_async("/reinitialize", handlers::reinitialize_server)
+        .post_async("/reinitialize", |req, ctx| api(req, ctx, handlers::reinitialize_server))
         // Todo routes
-        .post_async("/todos", handlers::create_todo)
-        .get_async("/todos", handlers::list_todos) 
-        .get_async("/todos/search", handlers::search_todos)
-        .get_async("/todos/resolve/:prefix", handlers::resolve_todo_prefix)
-        .get_async("/todos/:id", handlers::get_todo) // Keep parameterized routes last
-        .put_async("/todos/:id", handlers::update_todo)
-        .delete_async("/todos/:id", handlers::delete_todo)
-        .patch_async("/todos/:id/toggle", handlers::toggle_todo)
+        .post_async("/todos", |req, ctx| idempotent(req, requires(ctx, Scope::TodosWrite), handlers::create_todo))
+        .get_async("/todos", |req, ctx| api(req, requires(ctx, Scope::TodosRead), handlers::list_todos)) 
+        .post_async("/todos/batch", |req, ctx| idempotent(req, requires(ctx, Scope::TodosWrite), handlers::batch_todos))
+        .get_async("/todos/search", |req, ctx| api(req, requires(ctx, Scope::TodosRead), handlers::search_todos))
+        .get_async("/todos/resolve/:prefix", |req, ctx| api(req, requires(ctx, Scope::TodosRead), handlers::resolve_todo_prefix))
+        .get_async("/todos/:id", |req, ctx| api(req, requires(ctx, Scope::TodosRead), handlers::get_todo)) // Keep parameterized routes last
+        .put_async("/todos/:id", |req, ctx| idempotent(req, requires(ctx, Scope::TodosWrite), handlers::update_todo))
+        .patch_async("/todos/:id", |req, ctx| idempotent(req, requires(ctx, Scope::TodosWrite), handlers::patch_todo))
+        .delete_async("/todos/:id", |req, ctx| idempotent(req, requires(ctx, Scope::TodosDelete), handlers::delete_todo))
+        .patch_async("/todos/:id/toggle", |req, ctx| idempotent(req, requires(ctx, Scope::TodosWrite), handlers::toggle_todo))
+        // Delta sync for offline clients
+        .get_async("/sync", |req, ctx| api(req, requires(ctx, Scope::TodosRead), handlers::sync_todos))
+        // Tag routes
+        .get_async("/tags", |req, ctx| api(req, requires(ctx, Scope::TodosRead), handlers::list_tags))
+        .post_async("/tags/rename", |req, ctx| idempotent(req, requires(ctx, Scope::TodosWrite), handlers::rename_tag))
         // Admin routes  
-        .post_async("/admin/keys/rotate", handlers::rotate_admin_key)
-        .post_async("/admin/keys/generate", handlers::create_api_key)
-        .get_async("/admin/keys", handlers::list_api_keys)
-        .delete_async("/admin/keys/:id", handlers::revoke_api_key)
+        .post_async("/admin/keys/rotate", |req, ctx| api(req, ctx, handlers::rotate_own_api_key))
+        .post_async("/admin/keys/generate", |req, ctx| api(req, requires(ctx, Scope::KeysManage), handlers::create_api_key))
+        .get_async("/admin/keys", |req, ctx| api(req, requires(ctx, Scope::KeysManage), handlers::list_api_keys))
+        .get_async("/admin/audit", |req, ctx| api(req, requires(ctx, Scope::AuditRead), handlers::list_audit_events))
+        .get_async("/admin/schema", |req, ctx| api(req, requires(ctx, Scope::SchemaManage), handlers::get_schema_status))
+        .post_async("/admin/schema/migrate", |req, ctx| api(req, requires(ctx, Scope::SchemaManage), handlers::migrate_schema))
+        .post_async("/admin/keys/purge", |req, ctx| idempotent(req, requires(ctx, Scope::KeysManage), handlers::purge_api_keys))
+        .get_async("/admin/keys/:id", |req, ctx| api(req, requires(ctx, Scope::KeysManage), handlers::get_api_key))
+        .patch_async("/admin/keys/:id", |req, ctx| idempotent(req, requires(ctx, Scope::KeysManage), handlers::update_api_key))
+        .delete_async("/admin/keys/:id", |req, ctx| idempotent(req, requires(ctx, Scope::KeysManage), handlers::revoke_api_key))
+        .post_async("/admin/keys/:id/rotate", |req, ctx| api(req, requires(ctx, Scope::KeysManage), handlers::rotate_other_api_key))
+        .put_async("/admin/keys/:id/expiry", |req, ctx| idempotent(req, requires(ctx, Scope::KeysManage), handlers::set_api_key_expiry))
         .run(req, env)
         .await
-}
\ No newline at end of file
+}
+// Requests through the full router against an in-memory SQLite database, as the self-hosted
+// server would serve them
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use serde_json::{json, Value};
+    use crate::db::Database;
+    use crate::models::legacy_hash_api_key;
+    use crate::storage::{block_on, SqliteStorage, Statement, Storage};
+
+    fn migrated_env() -> Result<Env> {
+        let storage = Arc::new(SqliteStorage::open_in_memory()?);
+        block_on(schema::migrate(&Database::with_storage(Arc::clone(&storage))))?;
+        Ok(Env::new(storage))
+    }
+
+    // Send one request and return its status, headers and JSON body
+    fn send(env: &Env, method: Method, path: &str, headers: &[(&str, &str)], body: Option<Value>) -> Result<(u16, Headers, Value)> {
+        let mut request_headers = Headers::new();
+        for (name, value) in headers {
+            request_headers.set(name, value)?;
+        }
+        let body = match body {
+            Some(body) => {
+                request_headers.set("Content-Type", "application/json")?;
+                serde_json::to_vec(&body)?
+            },
+            None => Vec::new(),
+        };
+        let url = Url::parse(&format!("http://localhost{path}"))?;
+        
+        let response = block_on(handle(Request::new(method, url, request_headers, body), env.clone()))?;
+        let json = serde_json::from_slice(response.body()).unwrap_or(Value::Null);
+        Ok((response.status_code(), response.headers().clone(), json))
+    }
+
+    fn initialize(env: &Env) -> Result<String> {
+        let (status, _, body) = send(env, Method::Post, "/initialize", &[], None)?;
+        assert_eq!(status, 200, "{body}");
+        Ok(body["data"]["api_key"].as_str().unwrap_or_default().to_string())
+    }
+
+    fn generate_key(env: &Env, admin_key: &str, key: Value) -> Result<String> {
+        let (status, _, body) = send(env, Method::Post, "/admin/keys/generate", &[("X-API-Key", admin_key)], Some(key))?;
+        assert_eq!(status, 200, "{body}");
+        Ok(body["data"]["api_key"].as_str().unwrap_or_default().to_string())
+    }
+
+    #[test]
+    fn todo_routes_scope_by_key_and_honour_if_match() -> Result<()> {
+        let env = migrated_env()?;
+        let admin = initialize(&env)?;
+        let alice = generate_key(&env, &admin, json!({ "client_name": "alice", "key_type": "Client" }))?;
+        let bob = generate_key(&env, &admin, json!({ "client_name": "bob", "key_type": "Client" }))?;
+        
+        let (status, _, _) = send(&env, Method::Get, "/todos", &[], None)?;
+        assert_eq!(status, 401);
+        
+        let (status, headers, created) = send(&env, Method::Post, "/todos", &[("X-API-Key", &alice)], Some(json!({ "title": "Alice's", "tags": ["home"] })))?;
+        assert_eq!(status, 200, "{created}");
+        let id = created["data"]["id"].as_str().unwrap_or_default().to_string();
+        let etag = headers.get("ETag")?.unwrap_or_default();
+        
+        let (status, _, body) = send(&env, Method::Get, &format!("/todos/{id}"), &[("X-API-Key", &bob)], None)?;
+        assert_eq!((status, body["code"].as_str()), (404, Some("todo.not_found")));
+        
+        let (status, _, _) = send(&env, Method::Put, &format!("/todos/{id}"), &[("X-API-Key", &alice), ("If-Match", &etag)], Some(json!({ "title": "Renamed" })))?;
+        assert_eq!(status, 200);
+        let (status, _, body) = send(&env, Method::Put, &format!("/todos/{id}"), &[("X-API-Key", &alice), ("If-Match", &etag)], Some(json!({ "title": "Lost update" })))?;
+        assert_eq!((status, body["code"].as_str()), (412, Some("todo.version_mismatch")));
+        
+        let (_, _, body) = send(&env, Method::Get, &format!("/todos/{id}"), &[("X-API-Key", &alice)], None)?;
+        assert_eq!(body["data"]["title"], "Renamed");
+        Ok(())
+    }
+
+    #[test]
+    fn client_keys_cannot_reach_admin_routes() -> Result<()> {
+        let env = migrated_env()?;
+        let admin = initialize(&env)?;
+        let client = generate_key(&env, &admin, json!({ "client_name": "client", "key_type": "Client" }))?;
+        
+        let (status, _, body) = send(&env, Method::Get, "/admin/keys", &[("X-API-Key", &client)], None)?;
+        assert_eq!((status, body["code"].as_str()), (403, Some("auth.admin_required")));
+        let (status, _, _) = send(&env, Method::Get, "/admin/keys", &[("X-API-Key", &admin)], None)?;
+        assert_eq!(status, 200);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_batch_writes_nothing() -> Result<()> {
+        let env = migrated_env()?;
+        let admin = initialize(&env)?;
+        let key = [("X-API-Key", admin.as_str())];
+        
+        let (_, _, created) = send(&env, Method::Post, "/todos", &key, Some(json!({ "title": "Pinned" })))?;
+        let id = created["data"]["id"].as_str().unwrap_or_default().to_string();
+        let operations = json!({ "operations": [
+            { "op": "create", "todo": { "title": "Never created" } },
+            { "op": "update", "id": id, "changes": { "title": "Never renamed" }, "version": 99 },
+        ] });
+        let (status, _, body) = send(&env, Method::Post, "/todos/batch", &key, Some(operations))?;
+        assert_eq!((status, &body["success"]), (409, &json!(false)));
+        
+        let (_, _, listed) = send(&env, Method::Get, "/todos", &key, None)?;
+        assert_eq!((listed.get("error"), &listed["has_more"]), (Some(&Value::Null), &json!(false)));
+        let titles: Vec<&str> = listed["data"].as_array().into_iter().flatten().filter_map(|todo| todo["title"].as_str()).collect();
+        assert_eq!(titles, vec!["Pinned"]);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_returns_deleted_todos() -> Result<()> {
+        let env = migrated_env()?;
+        let admin = initialize(&env)?;
+        let key = [("X-API-Key", admin.as_str())];
+        
+        let (_, _, created) = send(&env, Method::Post, "/todos", &key, Some(json!({ "title": "Short-lived" })))?;
+        let id = created["data"]["id"].as_str().unwrap_or_default().to_string();
+        let (_, _, before) = send(&env, Method::Get, "/sync", &key, None)?;
+        let token = before["data"]["next_token"].as_str().unwrap_or_default().to_string();
+        let (status, _, _) = send(&env, Method::Delete, &format!("/todos/{id}"), &key, None)?;
+        assert_eq!(status, 200);
+        
+        let (_, _, after) = send(&env, Method::Get, &format!("/sync?since={token}"), &key, None)?;
+        assert_eq!(after["data"]["deleted"][0]["id"], id);
+        assert_eq!(after["data"]["changed"], json!([]));
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_admin_key_can_migrate_a_version_1_schema() -> Result<()> {
+        let storage = Arc::new(SqliteStorage::open_in_memory()?);
+        let db = Database::with_storage(Arc::clone(&storage));
+        let initial = &schema::MIGRATIONS[0];
+        block_on(db.apply_migration(&format!("{}.sql", initial.name), &schema::split_statements(initial.sql)))?;
+        
+        // Keys issued before lookup ids: pali_<secret>, stored under the unsalted legacy hash
+        let legacy_key = format!("pali_{}", "ab".repeat(32));
+        let insert = Statement::new("INSERT INTO api_keys (id, key_hash, client_name, key_type, created_at, active) VALUES ('legacy', ?1, 'Legacy admin', 'admin', 0, 1)");
+        block_on(storage.execute(insert.bind(&[legacy_hash_api_key(&legacy_key).into()])))?;
+        
+        let env = Env::new(storage);
+        let (status, _, body) = send(&env, Method::Post, "/admin/schema/migrate", &[("X-API-Key", &legacy_key)], None)?;
+        assert_eq!(status, 200, "{body}");
+        assert_eq!(block_on(schema::current_version(&db))?, schema::expected_version());
+        
+        let (status, _, _) = send(&env, Method::Get, "/todos", &[("X-API-Key", &legacy_key)], None)?;
+        assert_eq!(status, 200);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_auth_audit_is_throttled_per_client_ip() -> Result<()> {
+        let env = migrated_env()?;
+        let admin = initialize(&env)?;
+        for _ in 0..15 {
+            let (status, _, _) = send(&env, Method::Get, "/todos", &[("CF-Connecting-IP", "203.0.113.7")], None)?;
+            assert_eq!(status, 401);
+        }
+        send(&env, Method::Get, "/todos", &[("CF-Connecting-IP", "198.51.100.1")], None)?;
+        
+        let audited = |client_ip: &str| -> Result<usize> {
+            let path = format!("/admin/audit?event_type=auth.failed&client_ip={client_ip}");
+            let (_, _, body) = send(&env, Method::Get, &path, &[("X-API-Key", &admin)], None)?;
+            Ok(body["data"].as_array().map_or(0, Vec::len))
+        };
+        assert_eq!(audited("203.0.113.7")?, 10);
+        assert_eq!(audited("198.51.100.1")?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn expiry_changes_require_the_key_scopes() -> Result<()> {
+        let env = migrated_env()?;
+        let admin = initialize(&env)?;
+        let manager = generate_key(&env, &admin, json!({ "client_name": "manager", "key_type": "Admin", "scopes": ["keys:manage", "todos:read"] }))?;
+        generate_key(&env, &admin, json!({ "client_name": "reader", "key_type": "Client", "scopes": ["todos:read"] }))?;
+        generate_key(&env, &admin, json!({ "client_name": "writer", "key_type": "Client" }))?;
+        
+        let (_, _, keys) = send(&env, Method::Get, "/admin/keys", &[("X-API-Key", &admin)], None)?;
+        let id_of = |name: &str| keys["data"].as_array().into_iter().flatten()
+            .find(|key| key["client_name"] == name)
+            .and_then(|key| key["id"].as_str())
+            .unwrap_or_default()
+            .to_string();
+        let extend = |id: String| send(&env, Method::Put, &format!("/admin/keys/{id}/expiry"), &[("X-API-Key", &manager)], Some(json!({ "expires_in": 3600 })));
+        
+        assert_eq!(extend(id_of("reader"))?.0, 200);
+        let (status, _, body) = extend(id_of("writer"))?;
+        assert_eq!((status, body["code"].as_str()), (403, Some("auth.scope_required")));
+        let (status, _, _) = extend(id_of("Initial Admin Key"))?;
+        assert_eq!(status, 403);
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+// Request pipeline shared by every route registered in lib.rs
+// Handlers return ApiResult and use the extractors below instead of repeating the API key,
+// scope and database checks; `api` maps their errors to responses and logs them the same way.
+// Routes name the scope they need with `requires` in lib.rs, and the extractor enforces it.
+// Every response carries the request's id in X-Request-Id, and error bodies repeat it.
+// Failed authentication is written to the audit trail as well as the console.
+// Requests that spent from a rate limit bucket also carry the X-RateLimit-* headers.
+
+use std::cell::Cell;
+use std::future::Future;
+use std::rc::Rc;
+use serde::de::DeserializeOwned;
+#[allow(clippy::wildcard_imports)]
+use crate::platform::*;
+use crate::auth::{client_ip, validate_api_key_from_request, has_scope, todo_scope, AuthContext};
+use crate::db::{Database, TodoScope};
+use crate::error::{ApiError, ApiResult};
+use crate::rate_limit::{self, RateLimitStatus};
+use crate::audit::{AuditAction, AuditContext};
+use crate::models::{KeyType, Scope};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// Per-request data every route receives as RouteContext::data
+#[derive(Clone)]
+pub struct RequestInfo {
+    pub request_id: String,
+    // Filled in by rate_limit.rs during the request, read back by `api` for the response headers
+    pub rate_limit: Rc<Cell<Option<RateLimitStatus>>>,
+    // Set per route by `requires`; None for routes that take no API key
+    pub required_scope: Option<Scope>,
+}
+
+impl RequestInfo {
+    pub fn generate() -> Self {
+        Self {
+            request_id: uuid::Uuid::new_v4().to_string(),
+            rate_limit: Rc::default(),
+            required_scope: None,
+        }
+    }
+}
+
+// Security logging helper
+fn log_auth_attempt(req: &Request, client_name: Option<&str>, success: bool) {
+    let status = if success { "SUCCESS" } else { "FAILED" };
+    let client = client_name.unwrap_or("unknown");
+    console_log!("AUTH {}: {} {} - client: {}", status, req.method(), req.path(), client);
+}
+
+// Route adapter argument: the handler needs a key holding `scope`
+// e.g. `api(req, requires(ctx, Scope::TodosRead), handlers::list_todos)`
+pub fn requires(mut ctx: RouteContext<RequestInfo>, scope: Scope) -> RouteContext<RequestInfo> {
+    ctx.data.required_scope = Some(scope);
+    ctx
+}
+
+// Run a handler and turn its error, if any, into the JSON error envelope
+// Server errors log their cause; client errors log only the code the client received
+pub async fn api<F, Fut>(req: Request, ctx: RouteContext<RequestInfo>, handler: F) -> Result<Response>
+where
+    F: FnOnce(Request, RouteContext<RequestInfo>) -> Fut,
+    Fut: Future<Output = ApiResult<Response>>,
+{
+    let method = req.method();
+    let path = req.path();
+    let request_id = ctx.data.request_id.clone();
+    let rate_limit = Rc::clone(&ctx.data.rate_limit);
+    
+    let mut response = match handler(req, ctx).await {
+        Ok(response) => response,
+        Err(e) => {
+            if e.status() >= 500 {
+                console_log!("ERROR {} {}: {} {} - {} (request {})", e.status(), e.code(), method, path, e, request_id);
+            } else {
+                console_log!("REJECTED {} {}: {} {} (request {})", e.status(), e.code(), method, path, request_id);
+            }
+            e.to_response(&request_id)?
+        }
+    };
+    response.headers_mut().set(REQUEST_ID_HEADER, &request_id)?;
+    if let Some(status) = rate_limit.get() {
+        status.set_headers(response.headers_mut())?;
+    }
+    Ok(response)
+}
+
+// The "DB" binding, for routes that run before any API key exists
+pub fn database(env: &Env) -> ApiResult<Database> {
+    env.d1("DB")
+        .map(Database::new)
+        .map_err(|_| ApiError::DatabaseNotConfigured)
+}
+
+// A request carrying a valid API key, plus the database it was checked against
+pub struct Authenticated {
+    pub auth: AuthContext,
+    pub db: Database,
+}
+
+impl Authenticated {
+    // Extract the caller from X-API-Key; every attempt is logged, failures audited per client IP up to a limit
+    // Valid keys then spend one request from their rate limit budget and must hold the route's scope
+    pub async fn from_request(req: &Request, ctx: &RouteContext<RequestInfo>) -> ApiResult<Self> {
+        let db = database(&ctx.env)?;
+        match validate_api_key_from_request(req, &ctx.env).await {
+            Some(auth) => {
+                log_auth_attempt(req, Some(&auth.client_name), true);
+                rate_limit::for_key(&db, &ctx.env, &ctx.data, &auth).await?;
+                let caller = Self { auth, db };
+                if let Some(scope) = ctx.data.required_scope {
+                    caller.require(scope)?;
+                }
+                Ok(caller)
+            },
+            None => {
+                log_auth_attempt(req, None, false);
+                if rate_limit::audit_failed_auth(&db, &client_ip(req)).await {
+                    AuditContext::new(req, &ctx.data, None)
+                        .record(&db, &ctx.env, AuditAction::AuthFailed, None, None).await;
+                }
+                Err(ApiError::InvalidApiKey)
+            }
+        }
+    }
+
+    // For checks beyond the route's own scope, e.g. deletes inside a batch
+    pub fn require(&self, scope: Scope) -> ApiResult<()> {
+        require_scope(&self.auth, scope)
+    }
+
+    // Record an event this caller caused (see audit.rs)
+    pub async fn audit(&self, req: &Request, ctx: &RouteContext<RequestInfo>, action: AuditAction, target_id: &str, changes: serde_json::Value) {
+        AuditContext::new(req, &ctx.data, Some(&self.auth.key_id))
+            .record(&self.db, &ctx.env, action, Some(target_id), Some(changes)).await;
+    }
+
+    // Which owners' todos this request may touch (see auth::todo_scope)
+    pub fn scope(&self, req: &Request) -> TodoScope {
+        todo_scope(req, &self.auth)
+    }
+}
+
+pub fn require_scope(auth: &AuthContext, scope: Scope) -> ApiResult<()> {
+    if has_scope(auth, scope) {
+        Ok(())
+    } else if scope.admin_only() && auth.key_type == KeyType::Client {
+        // Client keys can never hold these; keep the error they have always received
+        Err(ApiError::AdminRequired)
+    } else {
+        Err(ApiError::ScopeRequired(scope))
+    }
+}
+
+// A :name segment from the route pattern
+pub fn param<'a>(ctx: &'a RouteContext<RequestInfo>, name: &str) -> ApiResult<&'a str> {
+    ctx.param(name)
+        .map(String::as_str)
+        .ok_or_else(|| ApiError::MissingParameter(format!("Missing {} parameter", name)))
+}
+
+// Request body as JSON; anything that does not deserialize is a 400
+pub async fn json_body<T: DeserializeOwned>(req: &mut Request) -> ApiResult<T> {
+    req.json().await.map_err(|_| ApiError::InvalidJson)
+}
+
+// Like json_body, for endpoints whose body is optional: an empty body gives T's defaults
+pub async fn optional_json_body<T: DeserializeOwned + Default>(req: &mut Request) -> ApiResult<T> {
+    let body = req.text().await.map_err(|_| ApiError::InvalidJson)?;
+    if body.trim().is_empty() {
+        return Ok(T::default());
+    }
+    serde_json::from_str(&body).map_err(|_| ApiError::InvalidJson)
+}
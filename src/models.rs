@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use rand::{thread_rng, Rng};
 use pbkdf2::pbkdf2_hmac_array;
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 
 // Re-export shared types
 pub use pali_types::*;
@@ -16,25 +16,652 @@ pub struct ApiKey {
     pub last_used: Option<i64>,
     pub created_at: i64,
     pub active: bool,
+    pub rate_limit_per_minute: Option<u32>,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<i64>,  // None = never expires
+    pub owner_key_id: Option<String>,  // None = owns its todos itself
+    pub rotated_from: Option<String>,
+    pub replaced_by: Option<String>,
+    pub revoked_at: Option<i64>,
 }
 
+impl ApiKey {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    // Key whose todos this key works on: the first key in its rotation chain
+    pub fn todo_owner(&self) -> &str {
+        self.owner_key_id.as_deref().unwrap_or(&self.id)
+    }
+}
+
+// What an API key may do; each route in lib.rs names the scope it requires
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "todos:read")]
+    TodosRead,
+    #[serde(rename = "todos:write")]
+    TodosWrite,
+    #[serde(rename = "todos:delete")]
+    TodosDelete,
+    // ?all_owners=true reaches every key's todos instead of only the caller's
+    #[serde(rename = "todos:all_owners")]
+    TodosAllOwners,
+    #[serde(rename = "keys:manage")]
+    KeysManage,
+    #[serde(rename = "audit:read")]
+    AuditRead,
+    #[serde(rename = "schema:manage")]
+    SchemaManage,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 7] = [
+        Scope::TodosRead,
+        Scope::TodosWrite,
+        Scope::TodosDelete,
+        Scope::TodosAllOwners,
+        Scope::KeysManage,
+        Scope::AuditRead,
+        Scope::SchemaManage,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::TodosRead => "todos:read",
+            Scope::TodosWrite => "todos:write",
+            Scope::TodosDelete => "todos:delete",
+            Scope::TodosAllOwners => "todos:all_owners",
+            Scope::KeysManage => "keys:manage",
+            Scope::AuditRead => "audit:read",
+            Scope::SchemaManage => "schema:manage",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.as_str() == value)
+    }
+
+    // Scopes only admin keys may hold, so POST /reinitialize (which deactivates every admin key)
+    // is guaranteed to take them away from a compromised key
+    pub fn admin_only(self) -> bool {
+        !matches!(self, Scope::TodosRead | Scope::TodosWrite | Scope::TodosDelete)
+    }
+
+    // Scopes of a key created without explicit ones, including every key older than scopes
+    pub fn preset(key_type: &KeyType) -> Vec<Scope> {
+        match key_type {
+            KeyType::Admin => Self::ALL.to_vec(),
+            KeyType::Client => vec![Scope::TodosRead, Scope::TodosWrite, Scope::TodosDelete],
+        }
+    }
+}
+
+// api_keys.scopes column format: space-separated names; unknown names are ignored
+pub fn parse_scopes(stored: &str) -> Vec<Scope> {
+    let mut scopes: Vec<Scope> = stored.split_whitespace().filter_map(Scope::parse).collect();
+    scopes.sort_unstable();
+    scopes.dedup();
+    scopes
+}
+
+pub fn format_scopes(scopes: &[Scope]) -> String {
+    scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(" ")
+}
+
+// POST /admin/keys/generate body: the shared create request plus optional scopes (the preset for
+// key_type when omitted), a request budget replacing the default for the key's type (see rate_limit.rs)
+// and an expiry, given either as a Unix timestamp or in seconds from now
+// Scopes arrive as plain strings so unknown names are reported per field instead of as bad JSON
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyPayload {
+    #[serde(flatten)]
+    pub key: CreateApiKeyRequest,
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    #[serde(default)]
+    pub rate_limit_per_minute: Option<u32>,
+    #[serde(flatten)]
+    pub expiry: ApiKeyExpiry,
+}
+
+// A key's expiry as clients send it: at most one of the two; neither means the key never expires
+#[derive(Debug, Default, Deserialize)]
+pub struct ApiKeyExpiry {
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub expires_in: Option<i64>,  // Seconds from now
+}
+
+// POST /admin/keys/generate response: the shared new-key response plus what the key may do
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKeyResponse,
+    pub scopes: Vec<Scope>,
+    pub rate_limit_per_minute: Option<u32>,
+    pub expires_at: Option<i64>,
+}
+
+// POST /admin/keys/rotate body; without a grace period the ROTATION_GRACE_SECS default applies
+#[derive(Debug, Default, Deserialize)]
+pub struct RotateApiKeyPayload {
+    #[serde(default)]
+    pub grace_period_secs: Option<i64>,
+}
+
+// PATCH /admin/keys/:id body: rename a key and/or revoke (false) or reactivate (true) it
+#[derive(Debug, Deserialize)]
+pub struct UpdateApiKeyPayload {
+    #[serde(default)]
+    pub client_name: Option<String>,
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+
+// POST /admin/keys/purge body; without revoked_before, keys revoked over 30 days ago are purged
+#[derive(Debug, Default, Deserialize)]
+pub struct PurgeApiKeysPayload {
+    #[serde(default)]
+    pub revoked_before: Option<i64>,
+}
+
+// POST /admin/keys/purge response: ids deleted, and revoked ids kept because todos depend on them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PurgeApiKeysResponse {
+    pub purged: Vec<String>,
+    pub retained: Vec<String>,
+}
+
+// Rotation response: the successor key plus when the key it replaces stops working
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotatedApiKey {
+    #[serde(flatten)]
+    pub key: CreatedApiKey,
+    pub rotated_from: String,
+    pub previous_key_expires_at: i64,
+}
+
+// GET /admin/keys entry: the shared key info plus the key's scopes, own request budget, expiry
+// and rotation links, if any; `active` stays true once a key expires, `expired` tells the two apart
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKeyDetails {
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+    pub scopes: Vec<Scope>,
+    pub rate_limit_per_minute: Option<u32>,
+    pub expires_at: Option<i64>,
+    pub expired: bool,
+    pub rotated_from: Option<String>,
+    pub replaced_by: Option<String>,
+    pub revoked_at: Option<i64>,
+}
+
+impl From<ApiKey> for ApiKeyDetails {
+    fn from(key: ApiKey) -> Self {
+        let expired = key.is_expired(chrono::Utc::now().timestamp());
+        ApiKeyDetails {
+            info: ApiKeyInfo {
+                id: key.id,
+                client_name: key.client_name,
+                key_type: key.key_type,
+                last_used: key.last_used,
+                created_at: key.created_at,
+                active: key.active,
+            },
+            scopes: key.scopes,
+            rate_limit_per_minute: key.rate_limit_per_minute,
+            expires_at: key.expires_at,
+            expired,
+            rotated_from: key.rotated_from,
+            replaced_by: key.replaced_by,
+            revoked_at: key.revoked_at,
+        }
+    }
+}
+
+// Length of the public lookup id embedded in API keys (hex-encoded on the wire)
+const LOOKUP_ID_BYTES: usize = 6;
+
 // Generate cryptographically secure API key with 256 bits of entropy
-pub fn generate_api_key() -> String {
+// Format: pali_<lookup_id>_<secret>, where the lookup id is public and only locates the row
+// Returns (api_key, lookup_id)
+pub fn generate_api_key() -> (String, String) {
     let mut rng = thread_rng();
+    let lookup_id = hex::encode(rng.gen::<[u8; LOOKUP_ID_BYTES]>());
     let key_bytes: [u8; 32] = rng.gen(); // 256 bits of entropy
-    format!("pali_{}", hex::encode(key_bytes))
+    (format!("pali_{}_{}", lookup_id, hex::encode(key_bytes)), lookup_id)
 }
 
-// Hash API key using PBKDF2 with fixed salt for security
-// Uses server-wide salt for consistency without per-key storage
+// Public id embedded in current-format keys, or None for legacy keys (pali_<secret>)
+fn embedded_lookup_id(key: &str) -> Option<&str> {
+    let rest = key.strip_prefix("pali_")?;
+    let (lookup_id, secret) = rest.split_once('_')?;
+    
+    if lookup_id.len() == LOOKUP_ID_BYTES * 2 && !secret.is_empty() {
+        Some(lookup_id)
+    } else {
+        None
+    }
+}
+
+// Keys issued before lookup ids existed
+pub fn is_legacy_api_key(key: &str) -> bool {
+    embedded_lookup_id(key).is_none()
+}
+
+// Lookup id used to find a key's row: the embedded id, or a SHA-256 of the whole key for legacy keys
+// A fast digest is fine here since keys carry 256 bits of entropy and the id only locates the row
+pub fn api_key_lookup_id(key: &str) -> String {
+    match embedded_lookup_id(key) {
+        Some(lookup_id) => lookup_id.to_string(),
+        None => hex::encode(Sha256::digest(key.as_bytes())),
+    }
+}
+
+// Stored hash format: pbkdf2-sha256$<iterations>$<salt hex>$<digest hex>
+// Rows written before the format was versioned hold a bare hex digest salted with LEGACY_SERVER_SALT
+const HASH_SCHEME_PBKDF2_SHA256: &str = "pbkdf2-sha256";
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_BYTES: usize = 16;
+const LEGACY_SERVER_SALT: &[u8] = b"pali_server_salt_2024_secure_todo_api_v1";
+const LEGACY_ITERATIONS: u32 = 100_000;
+
+enum StoredKeyHash {
+    Legacy { digest: Vec<u8> },
+    Pbkdf2Sha256 { iterations: u32, salt: Vec<u8>, digest: Vec<u8> },
+}
+
+impl StoredKeyHash {
+    fn parse(stored: &str) -> Option<Self> {
+        let parts: Vec<&str> = stored.split('$').collect();
+        match parts.as_slice() {
+            [HASH_SCHEME_PBKDF2_SHA256, iterations, salt, digest] => Some(StoredKeyHash::Pbkdf2Sha256 {
+                iterations: iterations.parse().ok()?,
+                salt: hex::decode(salt).ok()?,
+                digest: hex::decode(digest).ok()?,
+            }),
+            [digest] => Some(StoredKeyHash::Legacy { digest: hex::decode(digest).ok()? }),
+            _ => None,
+        }
+    }
+}
+
+fn pbkdf2_sha256(key: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    pbkdf2_hmac_array::<Sha256, 32>(key.as_bytes(), salt, iterations)
+}
+
+// Compare two byte strings without short-circuiting on the first difference
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Hash API key using PBKDF2-SHA256 with a random per-key salt
+// Parameters are encoded next to the digest so they can change without invalidating old rows
 pub fn hash_api_key(key: &str) -> String {
-    // Use fixed server-wide salt (better than key-derived salt)
-    // In production, consider storing random salts per key in database
-    const SERVER_SALT: &[u8] = b"pali_server_salt_2024_secure_todo_api_v1";
+    let salt: [u8; SALT_BYTES] = thread_rng().gen();
+    let digest = pbkdf2_sha256(key, &salt, PBKDF2_ITERATIONS);
+    format!(
+        "{}${}${}${}",
+        HASH_SCHEME_PBKDF2_SHA256,
+        PBKDF2_ITERATIONS,
+        hex::encode(salt),
+        hex::encode(digest)
+    )
+}
+
+// Unversioned hash from before per-key salts; only needed to find rows that were never upgraded
+pub fn legacy_hash_api_key(key: &str) -> String {
+    hex::encode(pbkdf2_sha256(key, LEGACY_SERVER_SALT, LEGACY_ITERATIONS))
+}
+
+// Verify a key against a stored hash in any supported format
+pub fn verify_api_key(key: &str, stored: &str) -> bool {
+    match StoredKeyHash::parse(stored) {
+        Some(StoredKeyHash::Pbkdf2Sha256 { iterations, salt, digest }) => {
+            constant_time_eq(&pbkdf2_sha256(key, &salt, iterations), &digest)
+        },
+        Some(StoredKeyHash::Legacy { digest }) => {
+            constant_time_eq(&pbkdf2_sha256(key, LEGACY_SERVER_SALT, LEGACY_ITERATIONS), &digest)
+        },
+        None => false,
+    }
+}
+
+// Whether a stored hash is weaker than what hash_api_key produces today
+pub fn needs_rehash(stored: &str) -> bool {
+    match StoredKeyHash::parse(stored) {
+        Some(StoredKeyHash::Pbkdf2Sha256 { iterations, salt, .. }) => {
+            iterations < PBKDF2_ITERATIONS || salt.len() < SALT_BYTES
+        },
+        _ => true,
+    }
+}
+
+// Todo as returned by the API: the shared Todo plus server-side version and tags
+// version increases on every write and doubles as the todo's ETag
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TodoResponse {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub version: i64,
+    pub tags: Vec<String>,
+}
+
+// Strong ETag for a todo version
+pub fn todo_etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+// POST /todos body: the shared create request plus optional tags
+#[derive(Debug, Deserialize)]
+pub struct CreateTodoPayload {
+    #[serde(flatten)]
+    pub todo: CreateTodoRequest,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// PUT /todos/:id body: omitted tags leave them unchanged, a list replaces them
+#[derive(Debug, Deserialize)]
+pub struct UpdateTodoPayload {
+    #[serde(flatten)]
+    pub todo: UpdateTodoRequest,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+}
+
+// One invalid field in a request body
+// code is stable (e.g. "validation.priority_range"); message is for humans and may change
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &str, message: &str) -> Self {
+        Self { field: field.to_string(), code: code.to_string(), message: message.to_string() }
+    }
+}
+
+// Envelope for every failed request: ApiResponse's shape plus a stable machine-readable code,
+// optional structured details and the id of the request (also sent as X-Request-Id)
+// Validation failures additionally list each invalid field
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub success: bool,
+    pub data: Option<()>,
+    pub error: String,
+    pub code: String,
+    pub details: Option<serde_json::Value>,
+    pub request_id: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+impl ErrorResponse {
+    pub fn new(error: String, code: &str, details: Option<serde_json::Value>, request_id: &str) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error,
+            code: code.to_string(),
+            details,
+            request_id: request_id.to_string(),
+            fields: Vec::new(),
+        }
+    }
+}
+
+// PATCH /todos/:id body after applying RFC 7396 rules: an absent member leaves the field
+// alone (None), null clears it (Some(None)); fields that cannot be null reject it instead
+#[derive(Debug, Default)]
+pub struct TodoPatch {
+    pub title: Option<String>,
+    pub description: Option<Option<String>>,
+    pub completed: Option<bool>,
+    pub priority: Option<i32>,
+    pub due_date: Option<Option<i64>>,
+    pub tags: Option<Vec<String>>,  // null clears all tags
+}
+
+impl TodoPatch {
+    // Validate a merge patch document, collecting every problem rather than stopping at the first
+    pub fn from_merge_patch(patch: &serde_json::Value) -> Result<Self, Vec<FieldError>> {
+        use serde_json::Value;
+        
+        let members = match patch.as_object() {
+            Some(members) => members,
+            None => return Err(vec![FieldError::new("", "validation.not_object", "merge patch must be a JSON object")]),
+        };
+        
+        let mut result = TodoPatch::default();
+        let mut errors = Vec::new();
+        
+        for (field, value) in members {
+            match (field.as_str(), value) {
+                ("title", Value::String(title)) => result.title = Some(title.clone()),
+                ("title", Value::Null) => errors.push(FieldError::new(field, "validation.not_nullable", "cannot be null")),
+                ("title", _) => errors.push(FieldError::new(field, "validation.invalid_type", "must be a string")),
+                
+                ("description", Value::Null) => result.description = Some(None),
+                ("description", Value::String(desc)) => result.description = Some(Some(desc.clone())),
+                ("description", _) => errors.push(FieldError::new(field, "validation.invalid_type", "must be a string or null")),
+                
+                ("completed", Value::Bool(completed)) => result.completed = Some(*completed),
+                ("completed", Value::Null) => errors.push(FieldError::new(field, "validation.not_nullable", "cannot be null")),
+                ("completed", _) => errors.push(FieldError::new(field, "validation.invalid_type", "must be a boolean")),
+                
+                ("priority", Value::Number(number)) => match number.as_i64() {
+                    Some(priority @ 1..=5) => result.priority = Some(priority as i32),
+                    _ => errors.push(FieldError::new(field, "validation.priority_range", "must be an integer between 1 and 5")),
+                },
+                ("priority", Value::Null) => errors.push(FieldError::new(field, "validation.not_nullable", "cannot be null")),
+                ("priority", _) => errors.push(FieldError::new(field, "validation.priority_range", "must be an integer between 1 and 5")),
+                
+                ("due_date", Value::Null) => result.due_date = Some(None),
+                ("due_date", Value::Number(number)) => match number.as_i64() {
+                    Some(due_date) => result.due_date = Some(Some(due_date)),
+                    None => errors.push(FieldError::new(field, "validation.invalid_type", "must be an integer timestamp or null")),
+                },
+                ("due_date", _) => errors.push(FieldError::new(field, "validation.invalid_type", "must be an integer timestamp or null")),
+                
+                ("tags", Value::Null) => result.tags = Some(Vec::new()),
+                ("tags", Value::Array(items)) => {
+                    let tags: Option<Vec<String>> = items.iter()
+                        .map(|item| item.as_str().map(str::to_string))
+                        .collect();
+                    match tags {
+                        Some(tags) => result.tags = Some(normalize_tags(&tags)),
+                        None => errors.push(FieldError::new(field, "validation.invalid_type", "must be an array of strings or null")),
+                    }
+                },
+                ("tags", _) => errors.push(FieldError::new(field, "validation.invalid_type", "must be an array of strings or null")),
+                
+                _ => errors.push(FieldError::new(field, "validation.unknown_field", "unknown field")),
+            }
+        }
+        
+        if errors.is_empty() {
+            Ok(result)
+        } else {
+            Err(errors)
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.description.is_none()
+            && self.completed.is_none()
+            && self.priority.is_none()
+            && self.due_date.is_none()
+            && self.tags.is_none()
+    }
+}
+
+// POST /todos/batch body
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+// One operation in a batch; `version` optionally pins the todo's version before the batch runs
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Create {
+        todo: CreateTodoPayload,
+    },
+    Update {
+        id: String,
+        changes: UpdateTodoPayload,
+        #[serde(default)]
+        version: Option<i64>,
+    },
+    Delete {
+        id: String,
+        #[serde(default)]
+        version: Option<i64>,
+    },
+    Toggle {
+        id: String,
+        #[serde(default)]
+        version: Option<i64>,
+    },
+}
+
+impl BatchOperation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatchOperation::Create { .. } => "create",
+            BatchOperation::Update { .. } => "update",
+            BatchOperation::Delete { .. } => "delete",
+            BatchOperation::Toggle { .. } => "toggle",
+        }
+    }
     
-    // Use PBKDF2 with 100,000 iterations for key stretching
-    let hash = pbkdf2_hmac_array::<Sha256, 32>(key.as_bytes(), SERVER_SALT, 100_000);
-    hex::encode(hash)
+    // Existing todo the operation targets, with its pinned version (None for creates)
+    pub fn target(&self) -> Option<(&str, Option<i64>)> {
+        match self {
+            BatchOperation::Create { .. } => None,
+            BatchOperation::Update { id, version, .. }
+            | BatchOperation::Delete { id, version }
+            | BatchOperation::Toggle { id, version } => Some((id.as_str(), *version)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Ok,
+    NotFound,
+    VersionMismatch,
+    Invalid,
+    NotApplied,  // Valid on its own, skipped because another operation failed
+}
+
+// Per-operation outcome, in request order; todo is the resulting state for create/update/toggle
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchOperationResult {
+    pub index: usize,
+    pub op: String,
+    pub id: Option<String>,
+    pub status: BatchStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<TodoResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// POST /todos/batch response: either every operation was applied or none were
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchResponse {
+    pub success: bool,
+    pub results: Vec<BatchOperationResult>,
+}
+
+// Tag with the number of todos carrying it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagInfo {
+    pub name: String,
+    pub todo_count: i64,
+}
+
+// POST /tags/rename body; renaming onto an existing tag merges the two
+#[derive(Debug, Deserialize)]
+pub struct RenameTagRequest {
+    pub from: String,
+    pub to: String,
+}
+
+// Tags are trimmed and lowercased so "Work" and " work" are the same tag
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+// Normalize a tag list, dropping empty and duplicate entries
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = tags.iter()
+        .map(|tag| normalize_tag(tag))
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    normalized
+}
+
+// Highlighted fragments of a search hit; matched terms are wrapped in <mark></mark>
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchHighlights {
+    pub title: String,
+    pub description: Option<String>,
+}
+
+// GET /todos/search hit: the todo plus its relevance (higher is better) and highlights
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub todo: TodoResponse,
+    pub score: f64,
+    pub highlights: SearchHighlights,
+}
+
+// A todo deleted since the client's last sync
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub id: String,
+    pub deleted_at: i64,
+}
+
+// GET /sync payload: everything that changed after the client's token, oldest first
+// next_token is opaque to clients and is passed back as ?since= on the next call
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncResponse {
+    pub changed: Vec<TodoResponse>,
+    pub deleted: Vec<Tombstone>,
+    pub next_token: String,
+    pub has_more: bool,
+}
+
+// One recorded security or data event (GET /admin/audit)
+// changes maps each affected field to {"from": ..., "to": ...}; null on the side that did not exist
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEvent {
+    pub id: i64,  // Assigned by the database on insert
+    pub event_type: String,
+    pub actor_key_id: Option<String>,
+    pub target_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub request_id: Option<String>,
+    pub changes: Option<serde_json::Value>,
+    pub created_at: i64,
 }
 
 // Response for ID prefix resolution
@@ -0,0 +1,382 @@
+// Native stand-ins for the part of the worker API the handlers use
+// With the "server" feature, lib.rs routes requests through these types instead of worker's, so
+// handlers, auth and idempotency run unchanged outside Workers (see src/bin/pali-selfhost.rs).
+// They mirror worker's signatures, including the Result returns that cannot fail here.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::storage::SqliteStorage;
+
+pub use worker::{Error, Method, Result, Url};
+
+// Logs go to stdout where the worker macro would write to the JS console
+macro_rules! console_log {
+    ($($t:tt)*) => { println!($($t)*) };
+}
+pub(crate) use console_log;
+
+// The "DB" binding: one SQLite database shared by every request thread
+pub type D1Database = Arc<SqliteStorage>;
+
+// Header names are case-insensitive and stored lowercased, as fetch does
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Repeated headers are combined into one comma-separated value
+    pub fn get(&self, name: &str) -> Result<Option<String>> {
+        let name = name.to_ascii_lowercase();
+        let values: Vec<&str> = self.entries.iter()
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str())
+            .collect();
+        Ok(if values.is_empty() { None } else { Some(values.join(", ")) })
+    }
+
+    pub fn has(&self, name: &str) -> Result<bool> {
+        Ok(self.get(name)?.is_some())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = name.to_ascii_lowercase();
+        self.entries.retain(|(key, _)| *key != name);
+        self.entries.push((name, value.to_string()));
+        Ok(())
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) -> Result<()> {
+        self.entries.push((name.to_ascii_lowercase(), value.to_string()));
+        Ok(())
+    }
+
+    pub fn entries(&self) -> std::vec::IntoIter<(String, String)> {
+        self.entries.clone().into_iter()
+    }
+}
+
+#[derive(Debug)]
+pub struct Request {
+    method: Method,
+    url: Url,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: Method, url: Url, headers: Headers, body: Vec<u8>) -> Self {
+        Self { method, url, headers, body }
+    }
+
+    pub fn method(&self) -> Method {
+        self.method.clone()
+    }
+
+    pub fn path(&self) -> String {
+        self.url.path().to_string()
+    }
+
+    pub fn url(&self) -> Result<Url> {
+        Ok(self.url.clone())
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> Result<&mut Headers> {
+        Ok(&mut self.headers)
+    }
+
+    pub async fn json<B: DeserializeOwned>(&mut self) -> Result<B> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    pub async fn text(&mut self) -> Result<String> {
+        String::from_utf8(self.body.clone()).map_err(|e| Error::RustError(e.to_string()))
+    }
+
+    pub async fn bytes(&mut self) -> Result<Vec<u8>> {
+        Ok(self.body.clone())
+    }
+
+    #[allow(clippy::should_implement_trait)] // Same fallible signature as worker::Request::clone
+    pub fn clone(&self) -> Result<Self> {
+        Ok(Self {
+            method: self.method.clone(),
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    status_code: u16,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn with_body(body: Vec<u8>, content_type: &str) -> Result<Self> {
+        let mut headers = Headers::new();
+        headers.set("Content-Type", content_type)?;
+        Ok(Self { status_code: 200, headers, body })
+    }
+
+    pub fn from_json<B: Serialize>(value: &B) -> Result<Self> {
+        Self::with_body(serde_json::to_vec(value)?, "application/json")
+    }
+
+    pub fn ok(body: impl Into<String>) -> Result<Self> {
+        Self::with_body(body.into().into_bytes(), "text/plain;charset=UTF-8")
+    }
+
+    pub fn error(msg: impl Into<String>, status: u16) -> Result<Self> {
+        Ok(Self::ok(msg)?.with_status(status))
+    }
+
+    pub fn with_status(mut self, status_code: u16) -> Self {
+        self.status_code = status_code;
+        self
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn cloned(&mut self) -> Result<Self> {
+        Ok(Clone::clone(self))
+    }
+
+    pub async fn text(&mut self) -> Result<String> {
+        String::from_utf8(self.body.clone()).map_err(|e| Error::RustError(e.to_string()))
+    }
+}
+
+// Value of a [vars] entry or secret
+pub struct Var(String);
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// Bindings for the native server: the SQLite database, plus process environment variables
+// standing in for both wrangler.toml [vars] and `wrangler secret` values
+#[derive(Clone)]
+pub struct Env {
+    db: D1Database,
+}
+
+impl Env {
+    pub fn new(db: D1Database) -> Self {
+        Self { db }
+    }
+
+    pub fn d1(&self, binding: &str) -> Result<D1Database> {
+        match binding {
+            "DB" => Ok(Arc::clone(&self.db)),
+            _ => Err(Error::RustError(format!("No database bound as {}", binding))),
+        }
+    }
+
+    pub fn var(&self, binding: &str) -> Result<Var> {
+        std::env::var(binding)
+            .map(Var)
+            .map_err(|_| Error::RustError(format!("Environment variable {} is not set", binding)))
+    }
+
+    pub fn secret(&self, binding: &str) -> Result<Var> {
+        self.var(binding)
+    }
+}
+
+pub struct RouteContext<D> {
+    pub data: D,
+    pub env: Env,
+    params: HashMap<String, String>,
+}
+
+impl<D> RouteContext<D> {
+    pub fn param(&self, key: &str) -> Option<&String> {
+        self.params.get(key)
+    }
+}
+
+type Handler<'a, D> = Box<dyn Fn(Request, RouteContext<D>) -> Pin<Box<dyn Future<Output = Result<Response>> + 'a>> + 'a>;
+
+enum Segment {
+    Static(String),
+    Param(String),
+}
+
+struct Route<'a, D> {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler<'a, D>,
+}
+
+impl<D> Route<'_, D> {
+    // Path parameters when the path fits this route's pattern
+    fn matches(&self, path: &[&str]) -> Option<HashMap<String, String>> {
+        if path.len() != self.segments.len() {
+            return None;
+        }
+
+        let mut params = HashMap::new();
+        for (segment, part) in self.segments.iter().zip(path) {
+            match segment {
+                Segment::Static(expected) if expected == part => {},
+                Segment::Static(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), (*part).to_string());
+                },
+            }
+        }
+        Some(params)
+    }
+
+    fn static_segments(&self) -> usize {
+        self.segments.iter().filter(|segment| matches!(segment, Segment::Static(_))).count()
+    }
+}
+
+fn path_segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|part| !part.is_empty()).collect()
+}
+
+// Same pattern syntax and precedence as worker's router: `:name` captures one segment,
+// and a literal segment wins over a parameter wherever both would match
+pub struct Router<'a, D> {
+    data: D,
+    routes: Vec<Route<'a, D>>,
+}
+
+impl Default for Router<'_, ()> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router<'_, ()> {
+    pub fn new() -> Self {
+        Self { data: (), routes: Vec::new() }
+    }
+}
+
+impl<'a, D: Clone + 'a> Router<'a, D> {
+    // Router whose handlers all receive `data` as RouteContext::data
+    pub fn with_data(data: D) -> Self {
+        Self { data, routes: Vec::new() }
+    }
+
+    fn add(mut self, method: Method, pattern: &str, handler: Handler<'a, D>) -> Self {
+        let segments = path_segments(pattern).into_iter()
+            .map(|part| match part.strip_prefix(':') {
+                Some(name) => Segment::Param(name.to_string()),
+                None => Segment::Static(part.to_string()),
+            })
+            .collect();
+        self.routes.push(Route { method, segments, handler });
+        self
+    }
+
+    fn add_async<T>(self, method: Method, pattern: &str, func: fn(Request, RouteContext<D>) -> T) -> Self
+    where
+        T: Future<Output = Result<Response>> + 'a,
+    {
+        self.add(method, pattern, Box::new(move |req, ctx| Box::pin(func(req, ctx))))
+    }
+
+    pub fn get(self, pattern: &str, func: fn(Request, RouteContext<D>) -> Result<Response>) -> Self {
+        self.add(Method::Get, pattern, Box::new(move |req, ctx| {
+            let response = func(req, ctx);
+            Box::pin(async move { response })
+        }))
+    }
+
+    pub fn get_async<T>(self, pattern: &str, func: fn(Request, RouteContext<D>) -> T) -> Self
+    where
+        T: Future<Output = Result<Response>> + 'a,
+    {
+        self.add_async(Method::Get, pattern, func)
+    }
+
+    pub fn post_async<T>(self, pattern: &str, func: fn(Request, RouteContext<D>) -> T) -> Self
+    where
+        T: Future<Output = Result<Response>> + 'a,
+    {
+        self.add_async(Method::Post, pattern, func)
+    }
+
+    pub fn put_async<T>(self, pattern: &str, func: fn(Request, RouteContext<D>) -> T) -> Self
+    where
+        T: Future<Output = Result<Response>> + 'a,
+    {
+        self.add_async(Method::Put, pattern, func)
+    }
+
+    pub fn patch_async<T>(self, pattern: &str, func: fn(Request, RouteContext<D>) -> T) -> Self
+    where
+        T: Future<Output = Result<Response>> + 'a,
+    {
+        self.add_async(Method::Patch, pattern, func)
+    }
+
+    pub fn delete_async<T>(self, pattern: &str, func: fn(Request, RouteContext<D>) -> T) -> Self
+    where
+        T: Future<Output = Result<Response>> + 'a,
+    {
+        self.add_async(Method::Delete, pattern, func)
+    }
+
+    pub async fn run(self, req: Request, env: Env) -> Result<Response> {
+        let path = req.path();
+        let path = path_segments(&path);
+
+        let mut candidates: Vec<(&Route<'a, D>, HashMap<String, String>)> = self.routes.iter()
+            .filter_map(|route| route.matches(&path).map(|params| (route, params)))
+            .collect();
+        if candidates.is_empty() {
+            return Response::error("Not Found", 404);
+        }
+
+        // Most specific pattern first; the sort is stable, so ties keep registration order
+        candidates.sort_by_key(|(route, _)| std::cmp::Reverse(route.static_segments()));
+        let method = req.method();
+        let matched = candidates.into_iter().find(|(route, _)| route.method == method);
+
+        match matched {
+            Some((route, params)) => {
+                let ctx = RouteContext { data: self.data.clone(), env, params };
+                (route.handler)(req, ctx).await
+            },
+            None => Response::error("Method Not Allowed", 405),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+// Cursor-based pagination shared by the list endpoints
+// Cursors are opaque to clients: hex-encoded JSON of the last returned row's sort key.
+// Each endpoint defines its own cursor struct matching its ORDER BY columns (see db.rs).
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use worker::Url;
+
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+pub const MAX_PAGE_LIMIT: usize = 200;
+
+// Parsed ?limit= and ?cursor= query parameters
+#[derive(Debug, Clone)]
+pub struct PageRequest<C> {
+    pub limit: usize,
+    pub after: Option<C>,
+}
+
+impl<C: DeserializeOwned> PageRequest<C> {
+    // Returns a client-facing message when either parameter is malformed
+    pub fn from_url(url: &Url) -> Result<Self, String> {
+        let limit = parse_limit(url)?;
+
+        let after = match url.query_pairs().find(|(key, _)| key == "cursor") {
+            Some((_, value)) => Some(decode_cursor(&value).ok_or_else(|| "Invalid cursor".to_string())?),
+            None => None,
+        };
+
+        Ok(Self { limit, after })
+    }
+}
+
+// ?limit= on its own, for endpoints that page by something other than a cursor
+pub fn parse_limit(url: &Url) -> Result<usize, String> {
+    match url.query_pairs().find(|(key, _)| key == "limit") {
+        Some((_, value)) => match value.parse::<usize>() {
+            Ok(limit) if (1..=MAX_PAGE_LIMIT).contains(&limit) => Ok(limit),
+            _ => Err(format!("limit must be between 1 and {}", MAX_PAGE_LIMIT)),
+        },
+        None => Ok(DEFAULT_PAGE_LIMIT),
+    }
+}
+
+impl<C> PageRequest<C> {
+    // Queries fetch one extra row to learn whether another page exists
+    pub fn fetch_limit(&self) -> usize {
+        self.limit + 1
+    }
+}
+
+pub fn encode_cursor<C: Serialize>(cursor: &C) -> String {
+    hex::encode(serde_json::to_vec(cursor).unwrap_or_default())
+}
+
+fn decode_cursor<C: DeserializeOwned>(raw: &str) -> Option<C> {
+    let bytes = hex::decode(raw).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+// One page of results plus the cursor for the next page, if any
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    // Build a page from up to limit + 1 rows; cursor_of extracts the sort key of the last kept row
+    pub fn from_rows<C: Serialize>(mut rows: Vec<T>, limit: usize, cursor_of: impl Fn(&T) -> C) -> Self {
+        let has_more = rows.len() > limit;
+        rows.truncate(limit);
+
+        let next_cursor = if has_more {
+            rows.last().map(|last| encode_cursor(&cursor_of(last)))
+        } else {
+            None
+        };
+
+        Self { items: rows, next_cursor }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+// Response envelope for paginated endpoints
+// `data` stays a plain array and `error` stays null, as in ApiResponse, so existing clients keep
+// working; paging info sits alongside them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PagedResponse<T> {
+    pub success: bool,
+    pub data: Vec<T>,
+    pub error: Option<String>,
+    pub next_cursor: Option<String>,
+    pub has_more: bool,
+}
+
+impl<T> From<Page<T>> for PagedResponse<T> {
+    fn from(page: Page<T>) -> Self {
+        Self {
+            success: true,
+            error: None,
+            has_more: page.next_cursor.is_some(),
+            next_cursor: page.next_cursor,
+            data: page.items,
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Per-key and per-IP request budgets, enforced with token buckets stored in D1 (migration 0013)
+// Every API key spends from its own bucket, sized by its key type unless the key carries its own
+// rate_limit_per_minute; the unauthenticated /initialize and /reinitialize spend from a bucket per
+// client IP. Budgets are requests per minute, refilled continuously, so a full bucket allows a
+// burst of that many requests. Defaults can be overridden with [vars] in wrangler.toml.
+// Failed authentications are not refused here, but only the first few per client IP each minute
+// are written to the audit log, so a flood of bad keys cannot grow audit_events without bound.
+
+#[allow(clippy::wildcard_imports)]
+use crate::platform::*;
+use chrono::Utc;
+use crate::auth::AuthContext;
+use crate::db::Database;
+use crate::error::{ApiError, ApiResult};
+use crate::middleware::RequestInfo;
+use crate::models::KeyType;
+
+pub const LIMIT_HEADER: &str = "X-RateLimit-Limit";
+pub const REMAINING_HEADER: &str = "X-RateLimit-Remaining";
+pub const RESET_HEADER: &str = "X-RateLimit-Reset";
+
+const ADMIN_PER_MINUTE_VAR: &str = "RATE_LIMIT_ADMIN_PER_MINUTE";
+const CLIENT_PER_MINUTE_VAR: &str = "RATE_LIMIT_CLIENT_PER_MINUTE";
+const SETUP_PER_MINUTE_VAR: &str = "RATE_LIMIT_SETUP_PER_MINUTE";
+
+const DEFAULT_ADMIN_PER_MINUTE: u32 = 600;
+const DEFAULT_CLIENT_PER_MINUTE: u32 = 120;
+// /initialize and /reinitialize are needed once in a blue moon; this only stops hammering
+const DEFAULT_SETUP_PER_MINUTE: u32 = 10;
+const FAILED_AUTH_AUDITS_PER_MINUTE: u32 = 10;
+
+// A bucket's state after this request, reported in the X-RateLimit-* headers
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    // Seconds until the bucket is full again
+    pub reset_secs: u64,
+}
+
+impl RateLimitStatus {
+    pub fn set_headers(&self, headers: &mut Headers) -> Result<()> {
+        headers.set(LIMIT_HEADER, &self.limit.to_string())?;
+        headers.set(REMAINING_HEADER, &self.remaining.to_string())?;
+        headers.set(RESET_HEADER, &self.reset_secs.to_string())?;
+        Ok(())
+    }
+}
+
+fn per_minute(env: &Env, name: &str, default: u32) -> u32 {
+    env.var(name)
+        .ok()
+        .and_then(|value| value.to_string().parse::<u32>().ok())
+        .filter(|limit| *limit > 0)
+        .unwrap_or(default)
+}
+
+// Spend one request from the key's budget; RateLimited once it is used up
+pub async fn for_key(db: &Database, env: &Env, info: &RequestInfo, auth: &AuthContext) -> ApiResult<()> {
+    let default = match auth.key_type {
+        KeyType::Admin => per_minute(env, ADMIN_PER_MINUTE_VAR, DEFAULT_ADMIN_PER_MINUTE),
+        KeyType::Client => per_minute(env, CLIENT_PER_MINUTE_VAR, DEFAULT_CLIENT_PER_MINUTE),
+    };
+    take(db, info, &format!("key:{}", auth.key_id), Some(&auth.key_id), default).await
+}
+
+// Spend one request from the client IP's budget for /initialize and /reinitialize
+pub async fn for_setup(db: &Database, env: &Env, info: &RequestInfo, client_ip: &str) -> ApiResult<()> {
+    let limit = per_minute(env, SETUP_PER_MINUTE_VAR, DEFAULT_SETUP_PER_MINUTE);
+    take(db, info, &format!("ip:{}", client_ip), None, limit).await
+}
+
+// Whether a failed authentication from this client IP should still be audited
+// Spends from its own bucket and never sets the X-RateLimit-* headers of the 401 response
+pub async fn audit_failed_auth(db: &Database, client_ip: &str) -> bool {
+    let now = Utc::now().timestamp_millis() as f64 / 1000.0;
+    match db.take_rate_limit_token(&format!("auth_failed:{}", client_ip), None, FAILED_AUTH_AUDITS_PER_MINUTE, now).await {
+        Ok(Some(state)) => state.allowed != 0,
+        // Without the bucket table there is no audit table to flood either
+        Ok(None) | Err(_) => true,
+    }
+}
+
+// Each request is charged at most once, however many extractors ask
+// Storage failures let the request through: the bucket table does not exist until migration 0013
+// has run, and /initialize and the schema routes must keep working before that
+async fn take(db: &Database, info: &RequestInfo, bucket: &str, key_id: Option<&str>, per_minute: u32) -> ApiResult<()> {
+    if info.rate_limit.get().is_some() {
+        return Ok(());
+    }
+
+    let now = Utc::now().timestamp_millis() as f64 / 1000.0;
+
+    let state = match db.take_rate_limit_token(bucket, key_id, per_minute, now).await {
+        Ok(Some(state)) => state,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            console_log!("RATE LIMIT UNAVAILABLE: {} - {} (request {})", bucket, e, info.request_id);
+            return Ok(());
+        }
+    };
+
+    let capacity = state.capacity.max(1) as f64;
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let status = RateLimitStatus {
+        limit: state.capacity as u32,
+        remaining: state.tokens.max(0.0).floor() as u32,
+        reset_secs: ((capacity - state.tokens).max(0.0) * 60.0 / capacity).ceil() as u64,
+    };
+    info.rate_limit.set(Some(status));
+
+    if state.allowed != 0 {
+        return Ok(());
+    }
+
+    // Time until the next whole token, never less than a second
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let retry_after_secs = (((1.0 - state.tokens) * 60.0 / capacity).ceil() as u64).max(1);
+    Err(ApiError::RateLimited { retry_after_secs })
+}
@@ -0,0 +1,240 @@
+// Embedded schema migrations and the version check run before serving requests
+// The SQL files under migrations/ are compiled in, so the worker always knows which schema it
+// expects. Operators can keep using `wrangler d1 migrations apply`; the runner here records
+// applied files in wrangler's d1_migrations table too, so the two never re-apply each other's work.
+
+use std::cell::Cell;
+use serde::Serialize;
+#[allow(clippy::wildcard_imports)]
+use crate::platform::*;
+use crate::db::Database;
+use crate::storage::Storage;
+use crate::error::{ApiError, ApiResult};
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../migrations/", $name, ".sql")),
+        }
+    };
+}
+
+// Ordered list of every migration; append new files here
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_initial_schema"),
+    migration!(2, "0002_todo_ownership"),
+    migration!(3, "0003_recovery_attempts"),
+    migration!(4, "0004_api_key_lookup_id"),
+    migration!(5, "0005_tags"),
+    migration!(6, "0006_pagination_indexes"),
+    migration!(7, "0007_todos_fts"),
+    migration!(8, "0008_change_tracking"),
+    migration!(9, "0009_todo_versions"),
+    migration!(10, "0010_write_preconditions"),
+    migration!(11, "0011_idempotency_keys"),
+    migration!(12, "0012_schema_version"),
+    migration!(13, "0013_rate_limits"),
+    migration!(14, "0014_audit_events"),
+    migration!(15, "0015_api_key_scopes"),
+    migration!(16, "0016_api_key_expiry"),
+    migration!(17, "0017_api_key_rotation"),
+    migration!(18, "0018_api_key_revoked_at"),
+];
+
+pub fn expected_version() -> u32 {
+    MIGRATIONS.last().map(|migration| migration.version).unwrap_or(0)
+}
+
+// GET /admin/schema payload
+#[derive(Debug, Serialize)]
+pub struct SchemaStatus {
+    pub current_version: u32,
+    pub expected_version: u32,
+    pub up_to_date: bool,
+    pub pending: Vec<&'static str>,
+}
+
+impl SchemaStatus {
+    fn new(current_version: u32) -> Self {
+        Self {
+            current_version,
+            expected_version: expected_version(),
+            up_to_date: current_version >= expected_version(),
+            pending: pending_migrations(current_version).map(|migration| migration.name).collect(),
+        }
+    }
+}
+
+fn pending_migrations(current_version: u32) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS.iter().filter(move |migration| migration.version > current_version)
+}
+
+// Applied schema version; 0 means an empty database
+// Databases migrated before schema_version existed are inferred from wrangler's d1_migrations
+pub async fn current_version<S: Storage>(db: &Database<S>) -> Result<u32> {
+    let tables = db.table_names().await?;
+    let has_table = |name: &str| tables.iter().any(|table| table == name);
+    
+    if has_table("schema_version") {
+        return db.max_schema_version().await;
+    }
+    
+    if has_table("d1_migrations") {
+        let applied = db.applied_wrangler_migrations().await?;
+        let version = MIGRATIONS.iter()
+            .take_while(|migration| applied.iter().any(|name| name.trim_end_matches(".sql") == migration.name))
+            .last()
+            .map(|migration| migration.version);
+        if let Some(version) = version {
+            return Ok(version);
+        }
+    }
+    
+    // Tables without any migration bookkeeping can only come from the initial schema
+    Ok(if has_table("api_keys") { 1 } else { 0 })
+}
+
+pub async fn status<S: Storage>(db: &Database<S>) -> Result<SchemaStatus> {
+    Ok(SchemaStatus::new(current_version(db).await?))
+}
+
+// Apply every pending migration in order, each one atomically; returns the names applied
+pub async fn migrate<S: Storage>(db: &Database<S>) -> Result<Vec<&'static str>> {
+    let current = current_version(db).await?;
+    let mut applied = Vec::new();
+    
+    for migration in pending_migrations(current) {
+        db.apply_migration(&format!("{}.sql", migration.name), &split_statements(migration.sql)).await
+            .map_err(|e| Error::RustError(format!("Migration {} failed: {}", migration.name, e)))?;
+        applied.push(migration.name);
+    }
+    
+    mark_schema_current();
+    Ok(applied)
+}
+
+thread_local! {
+    // Once an isolate has seen an up-to-date schema it stops checking; schemas only move forward
+    static SCHEMA_CURRENT: Cell<bool> = const { Cell::new(false) };
+}
+
+fn mark_schema_current() {
+    SCHEMA_CURRENT.with(|current| current.set(true));
+}
+
+// Routes that must keep working while the schema is behind
+const SCHEMA_EXEMPT_PATHS: &[&str] = &["/", "/health", "/initialize", "/admin/schema", "/admin/schema/migrate"];
+
+// Refuse to serve with a clear error when the database is behind this build
+// Fails with SchemaOutdated (503) instead of letting the request reach a handler
+pub async fn schema_guard(req: &Request, env: &Env) -> ApiResult<()> {
+    if SCHEMA_CURRENT.with(Cell::get) || SCHEMA_EXEMPT_PATHS.contains(&req.path().as_str()) {
+        return Ok(());
+    }
+    
+    // A missing binding is reported by the handlers themselves
+    let d1 = match env.d1("DB") {
+        Ok(d1) => d1,
+        Err(_) => return Ok(()),
+    };
+    
+    let status = status(&Database::new(d1)).await?;
+    if status.up_to_date {
+        mark_schema_current();
+        return Ok(());
+    }
+    
+    Err(ApiError::SchemaOutdated {
+        current_version: status.current_version,
+        expected_version: status.expected_version,
+    })
+}
+
+// Split a migration file into individual statements for D1's prepared-statement API
+// Semicolons inside quotes, comments, and BEGIN/CASE ... END blocks (trigger bodies) do not split
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut chars = sql.chars().peekable();
+    
+    while let Some(c) = chars.next() {
+        match c {
+            // Line comment: skip to end of line
+            '-' if chars.peek() == Some(&'-') => {
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        current.push('\n');
+                        break;
+                    }
+                }
+            },
+            // Block comment
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+                current.push(' ');
+            },
+            // Quoted string or identifier; a doubled quote is an escaped quote and stays inside
+            '\'' | '"' | '`' => {
+                current.push(c);
+                while let Some(next) = chars.next() {
+                    current.push(next);
+                    if next == c {
+                        if chars.peek() == Some(&c) {
+                            current.push(c);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                }
+            },
+            ';' if depth == 0 => {
+                let statement = current.trim();
+                if !statement.is_empty() {
+                    statements.push(statement.to_string());
+                }
+                current.clear();
+            },
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut word = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || next == '_' {
+                        word.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match word.to_ascii_uppercase().as_str() {
+                    "BEGIN" | "CASE" => depth += 1,
+                    "END" => depth = depth.saturating_sub(1),
+                    _ => {},
+                }
+                current.push_str(&word);
+            },
+            _ => current.push(c),
+        }
+    }
+    
+    let statement = current.trim();
+    if !statement.is_empty() {
+        statements.push(statement.to_string());
+    }
+    statements
+}
@@ -0,0 +1,325 @@
+// Storage backends for Database
+// All SQL lives in db.rs and is written for SQLite; a backend only runs statements and batches
+// and hands the rows back. D1Storage serves the worker, while SqliteStorage (feature "native")
+// runs the same queries and migrations against an embedded SQLite file or in-memory database.
+
+use serde::de::DeserializeOwned;
+use wasm_bindgen::JsValue;
+use worker::*;
+
+// A value bound to a ?N placeholder
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl From<i64> for SqlValue {
+    fn from(value: i64) -> Self {
+        SqlValue::Integer(value)
+    }
+}
+
+impl From<i32> for SqlValue {
+    fn from(value: i32) -> Self {
+        SqlValue::Integer(value.into())
+    }
+}
+
+impl From<u16> for SqlValue {
+    fn from(value: u16) -> Self {
+        SqlValue::Integer(value.into())
+    }
+}
+
+impl From<usize> for SqlValue {
+    fn from(value: usize) -> Self {
+        SqlValue::Integer(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(value: f64) -> Self {
+        SqlValue::Real(value)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self {
+        SqlValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> Self {
+        SqlValue::Text(value)
+    }
+}
+
+// None binds as NULL
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(SqlValue::Null, Into::into)
+    }
+}
+
+// One SQL statement with its bound parameters, not yet tied to a backend
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub sql: String,
+    pub params: Vec<SqlValue>,
+}
+
+impl Statement {
+    pub fn new(sql: impl Into<String>) -> Self {
+        Self { sql: sql.into(), params: Vec::new() }
+    }
+
+    pub fn bind(mut self, params: &[SqlValue]) -> Self {
+        self.params = params.to_vec();
+        self
+    }
+}
+
+// Rows and write count from one executed statement
+pub trait StatementResult {
+    fn rows<T: DeserializeOwned>(&self) -> Result<Vec<T>>;
+
+    // Rows inserted, updated or deleted by the statement itself (0 for queries)
+    fn changes(&self) -> usize;
+}
+
+// Backends are only ever used through the concrete Database<S>, never as trait objects
+#[allow(async_fn_in_trait)]
+pub trait Storage {
+    type Output: StatementResult;
+
+    async fn execute(&self, statement: Statement) -> Result<Self::Output>;
+
+    // Run the statements in order as one transaction: an error anywhere rolls all of them back
+    async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<Self::Output>>;
+}
+
+pub struct D1Storage {
+    d1: D1Database,
+}
+
+impl D1Storage {
+    pub fn new(d1: D1Database) -> Self {
+        Self { d1 }
+    }
+
+    fn prepare(&self, statement: Statement) -> Result<D1PreparedStatement> {
+        let params: Vec<JsValue> = statement.params.into_iter().map(js_param).collect();
+        self.d1.prepare(statement.sql).bind(&params)
+    }
+}
+
+// WORKAROUND: D1 binding limitations
+// Issue: https://github.com/cloudflare/workers-rs/issues/678
+// D1 binding doesn't support Option<T> directly - None becomes "undefined",
+// which causes "Type 'undefined' not supported" errors, so NULL is passed explicitly.
+// Integers are sent as f64 because D1 rejects BigInt bindings.
+#[allow(clippy::cast_precision_loss)]
+fn js_param(value: SqlValue) -> JsValue {
+    match value {
+        SqlValue::Null => JsValue::NULL,
+        SqlValue::Integer(value) => (value as f64).into(),
+        SqlValue::Real(value) => value.into(),
+        SqlValue::Text(value) => value.into(),
+    }
+}
+
+impl StatementResult for D1Result {
+    fn rows<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.results::<T>()
+    }
+
+    fn changes(&self) -> usize {
+        self.meta().ok().flatten().and_then(|meta| meta.changes).unwrap_or(0)
+    }
+}
+
+impl Storage for D1Storage {
+    type Output = D1Result;
+
+    async fn execute(&self, statement: Statement) -> Result<D1Result> {
+        self.prepare(statement)?.all().await
+    }
+
+    // D1 runs a batch as a single implicit transaction
+    async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<D1Result>> {
+        let statements = statements.into_iter()
+            .map(|statement| self.prepare(statement))
+            .collect::<Result<Vec<_>>>()?;
+        self.d1.batch(statements).await
+    }
+}
+
+// Lets one backend be shared, e.g. by the native server's request threads
+impl<S: Storage> Storage for std::sync::Arc<S> {
+    type Output = S::Output;
+
+    async fn execute(&self, statement: Statement) -> Result<S::Output> {
+        S::execute(self, statement).await
+    }
+
+    async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<S::Output>> {
+        S::batch(self, statements).await
+    }
+}
+
+// Backend behind the "DB" binding the handlers receive
+#[cfg(not(feature = "server"))]
+pub type PlatformStorage = D1Storage;
+#[cfg(feature = "server")]
+pub type PlatformStorage = std::sync::Arc<SqliteStorage>;
+
+#[cfg(feature = "native")]
+pub use sqlite::{block_on, SqliteStorage};
+
+#[cfg(feature = "native")]
+mod sqlite {
+    use std::future::Future;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+    use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+    use rusqlite::{Connection, ToSql};
+    use serde::de::DeserializeOwned;
+    use worker::{Error, Result};
+    use super::{SqlValue, Statement, StatementResult, Storage};
+
+    // Embedded SQLite backend; run schema::migrate on a fresh database before serving
+    pub struct SqliteStorage {
+        conn: Mutex<Connection>,
+    }
+
+    impl SqliteStorage {
+        pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+            Self::from_connection(Connection::open(path).map_err(sqlite_error)?)
+        }
+
+        pub fn open_in_memory() -> Result<Self> {
+            Self::from_connection(Connection::open_in_memory().map_err(sqlite_error)?)
+        }
+
+        // D1 enforces foreign keys; plain SQLite only does when asked
+        fn from_connection(conn: Connection) -> Result<Self> {
+            conn.pragma_update(None, "foreign_keys", true).map_err(sqlite_error)?;
+            Ok(Self { conn: Mutex::new(conn) })
+        }
+
+        fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+            self.conn.lock().map_err(|_| Error::RustError("SQLite connection lock poisoned".to_string()))
+        }
+    }
+
+    // Rows are kept as JSON objects, the same shape D1 hands back
+    pub struct SqliteResult {
+        rows: Vec<serde_json::Value>,
+        changes: usize,
+    }
+
+    impl StatementResult for SqliteResult {
+        fn rows<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+            self.rows.iter()
+                .map(|row| serde_json::from_value(row.clone()).map_err(Error::from))
+                .collect()
+        }
+
+        fn changes(&self) -> usize {
+            self.changes
+        }
+    }
+
+    impl Storage for SqliteStorage {
+        type Output = SqliteResult;
+
+        async fn execute(&self, statement: Statement) -> Result<SqliteResult> {
+            let conn = self.lock()?;
+            run(&conn, &statement).map_err(sqlite_error)
+        }
+
+        async fn batch(&self, statements: Vec<Statement>) -> Result<Vec<SqliteResult>> {
+            let mut conn = self.lock()?;
+            let tx = conn.transaction().map_err(sqlite_error)?;
+            let results = statements.iter()
+                .map(|statement| run(&tx, statement))
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .map_err(sqlite_error)?;
+            tx.commit().map_err(sqlite_error)?;
+            Ok(results)
+        }
+    }
+
+    impl ToSql for SqlValue {
+        fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+            Ok(match self {
+                SqlValue::Null => ToSqlOutput::Owned(Value::Null),
+                SqlValue::Integer(value) => ToSqlOutput::Owned(Value::Integer(*value)),
+                SqlValue::Real(value) => ToSqlOutput::Owned(Value::Real(*value)),
+                SqlValue::Text(value) => ToSqlOutput::Borrowed(ValueRef::Text(value.as_bytes())),
+            })
+        }
+    }
+
+    fn run(conn: &Connection, statement: &Statement) -> rusqlite::Result<SqliteResult> {
+        let mut stmt = conn.prepare(&statement.sql)?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(str::to_string).collect();
+        let readonly = stmt.readonly();
+        
+        let mut rows = Vec::new();
+        let mut cursor = stmt.query(rusqlite::params_from_iter(&statement.params))?;
+        while let Some(row) = cursor.next()? {
+            let mut object = serde_json::Map::with_capacity(columns.len());
+            for (index, column) in columns.iter().enumerate() {
+                object.insert(column.clone(), json_value(row.get_ref(index)?));
+            }
+            rows.push(serde_json::Value::Object(object));
+        }
+        
+        let changes = if readonly { 0 } else { usize::try_from(conn.changes()).unwrap_or(usize::MAX) };
+        Ok(SqliteResult { rows, changes })
+    }
+
+    fn json_value(value: ValueRef<'_>) -> serde_json::Value {
+        match value {
+            ValueRef::Null => serde_json::Value::Null,
+            ValueRef::Integer(value) => value.into(),
+            ValueRef::Real(value) => value.into(),
+            ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned().into(),
+            ValueRef::Blob(blob) => hex::encode(blob).into(),
+        }
+    }
+
+    fn sqlite_error(e: rusqlite::Error) -> Error {
+        Error::RustError(e.to_string())
+    }
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Drive a future to completion on the current thread; native callers need no async runtime
+    // because SQLite calls are synchronous, so Database futures almost never actually wait
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+}
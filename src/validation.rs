@@ -0,0 +1,194 @@
+// Server-side validation for todo payloads
+// Runs before anything reaches D1, so bad input is reported per field (422) instead of surfacing
+// as a constraint failure (500). Limits default to the values below and can be overridden with
+// [vars] in wrangler.toml (or environment variables for the self-hosted server).
+
+use crate::platform::Env;
+use crate::models::{CreateTodoPayload, UpdateTodoPayload, TodoPatch, FieldError};
+
+const MAX_TITLE_CHARS_VAR: &str = "TODO_MAX_TITLE_CHARS";
+const MAX_DESCRIPTION_CHARS_VAR: &str = "TODO_MAX_DESCRIPTION_CHARS";
+const MAX_TAGS_VAR: &str = "TODO_MAX_TAGS";
+const MAX_TAG_CHARS_VAR: &str = "TODO_MAX_TAG_CHARS";
+const MIN_DUE_DATE_VAR: &str = "TODO_MIN_DUE_DATE";
+const MAX_DUE_DATE_VAR: &str = "TODO_MAX_DUE_DATE";
+
+const DEFAULT_MAX_TITLE_CHARS: usize = 500;
+const DEFAULT_MAX_DESCRIPTION_CHARS: usize = 10_000;
+const DEFAULT_MAX_TAGS: usize = 20;
+const DEFAULT_MAX_TAG_CHARS: usize = 50;
+// 1970-01-01 through 2100-01-01 (Unix seconds); anything outside is almost certainly a unit mix-up
+const DEFAULT_MIN_DUE_DATE: i64 = 0;
+const DEFAULT_MAX_DUE_DATE: i64 = 4_102_444_800;
+
+// Must match the CHECK constraint on todos.priority
+const PRIORITY_RANGE: std::ops::RangeInclusive<i32> = 1..=5;
+
+#[derive(Debug, Clone)]
+pub struct TodoLimits {
+    pub max_title_chars: usize,
+    pub max_description_chars: usize,
+    pub max_tags: usize,
+    pub max_tag_chars: usize,
+    pub min_due_date: i64,
+    pub max_due_date: i64,
+}
+
+impl Default for TodoLimits {
+    fn default() -> Self {
+        Self {
+            max_title_chars: DEFAULT_MAX_TITLE_CHARS,
+            max_description_chars: DEFAULT_MAX_DESCRIPTION_CHARS,
+            max_tags: DEFAULT_MAX_TAGS,
+            max_tag_chars: DEFAULT_MAX_TAG_CHARS,
+            min_due_date: DEFAULT_MIN_DUE_DATE,
+            max_due_date: DEFAULT_MAX_DUE_DATE,
+        }
+    }
+}
+
+fn var<T: std::str::FromStr>(env: &Env, name: &str) -> Option<T> {
+    env.var(name).ok().and_then(|value| value.to_string().parse::<T>().ok())
+}
+
+impl TodoLimits {
+    // Defaults overridden by any valid [vars] entries; malformed or zero values are ignored
+    pub fn from_env(env: &Env) -> Self {
+        let defaults = Self::default();
+        let count = |name: &str, default: usize| var::<usize>(env, name).filter(|n| *n > 0).unwrap_or(default);
+
+        let limits = Self {
+            max_title_chars: count(MAX_TITLE_CHARS_VAR, defaults.max_title_chars),
+            max_description_chars: count(MAX_DESCRIPTION_CHARS_VAR, defaults.max_description_chars),
+            max_tags: count(MAX_TAGS_VAR, defaults.max_tags),
+            max_tag_chars: count(MAX_TAG_CHARS_VAR, defaults.max_tag_chars),
+            min_due_date: var(env, MIN_DUE_DATE_VAR).unwrap_or(defaults.min_due_date),
+            max_due_date: var(env, MAX_DUE_DATE_VAR).unwrap_or(defaults.max_due_date),
+        };
+
+        if limits.min_due_date > limits.max_due_date {
+            Self { min_due_date: defaults.min_due_date, max_due_date: defaults.max_due_date, ..limits }
+        } else {
+            limits
+        }
+    }
+
+    pub fn validate_create(&self, payload: &CreateTodoPayload) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        self.check_title(&payload.todo.title, &mut errors);
+        self.check_description(payload.todo.description.as_deref(), &mut errors);
+        self.check_priority(payload.todo.priority, &mut errors);
+        self.check_due_date(payload.todo.due_date, &mut errors);
+        self.check_tags(&payload.tags, &mut errors);
+        finish(errors)
+    }
+
+    pub fn validate_update(&self, payload: &UpdateTodoPayload) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        if let Some(title) = &payload.todo.title {
+            self.check_title(title, &mut errors);
+        }
+        self.check_description(payload.todo.description.as_deref(), &mut errors);
+        self.check_priority(payload.todo.priority, &mut errors);
+        self.check_due_date(payload.todo.due_date, &mut errors);
+        if let Some(tags) = &payload.tags {
+            self.check_tags(tags, &mut errors);
+        }
+        finish(errors)
+    }
+
+    // Types and nullability are already checked by TodoPatch::from_merge_patch
+    pub fn validate_patch(&self, patch: &TodoPatch) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        if let Some(title) = &patch.title {
+            self.check_title(title, &mut errors);
+        }
+        self.check_description(patch.description.as_ref().and_then(Option::as_deref), &mut errors);
+        self.check_due_date(patch.due_date.flatten(), &mut errors);
+        if let Some(tags) = &patch.tags {
+            self.check_tags(tags, &mut errors);
+        }
+        finish(errors)
+    }
+
+    // Used for tag renames, where the name arrives on its own
+    pub fn validate_tag(&self, field: &str, tag: &str) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        self.check_tag(field, tag, &mut errors);
+        finish(errors)
+    }
+
+    fn check_title(&self, title: &str, errors: &mut Vec<FieldError>) {
+        if title.trim().is_empty() {
+            errors.push(FieldError::new("title", "validation.required", "must not be empty"));
+        } else if title.chars().count() > self.max_title_chars {
+            errors.push(too_long("title", self.max_title_chars));
+        }
+    }
+
+    fn check_description(&self, description: Option<&str>, errors: &mut Vec<FieldError>) {
+        if description.is_some_and(|description| description.chars().count() > self.max_description_chars) {
+            errors.push(too_long("description", self.max_description_chars));
+        }
+    }
+
+    fn check_priority(&self, priority: Option<i32>, errors: &mut Vec<FieldError>) {
+        if priority.is_some_and(|priority| !PRIORITY_RANGE.contains(&priority)) {
+            errors.push(FieldError::new("priority", "validation.priority_range", "must be an integer between 1 and 5"));
+        }
+    }
+
+    fn check_due_date(&self, due_date: Option<i64>, errors: &mut Vec<FieldError>) {
+        if due_date.is_some_and(|due_date| due_date < self.min_due_date || due_date > self.max_due_date) {
+            errors.push(FieldError::new(
+                "due_date",
+                "validation.due_date_range",
+                &format!("must be a Unix timestamp between {} and {}", self.min_due_date, self.max_due_date),
+            ));
+        }
+    }
+
+    // Counted after normalization, so duplicates and blank entries do not count against the limit
+    fn check_tags(&self, tags: &[String], errors: &mut Vec<FieldError>) {
+        let tags = crate::models::normalize_tags(tags);
+        if tags.len() > self.max_tags {
+            errors.push(FieldError::new(
+                "tags",
+                "validation.too_many",
+                &format!("must contain at most {} tags", self.max_tags),
+            ));
+        }
+        if let Some(tag) = tags.iter().find(|tag| tag.chars().count() > self.max_tag_chars) {
+            self.check_tag("tags", tag, errors);
+        }
+    }
+
+    fn check_tag(&self, field: &str, tag: &str, errors: &mut Vec<FieldError>) {
+        if tag.chars().count() > self.max_tag_chars {
+            errors.push(FieldError::new(
+                field,
+                "validation.too_long",
+                &format!("tags must be at most {} characters", self.max_tag_chars),
+            ));
+        }
+    }
+}
+
+fn too_long(field: &str, max_chars: usize) -> FieldError {
+    FieldError::new(field, "validation.too_long", &format!("must be at most {} characters", max_chars))
+}
+
+fn finish(errors: Vec<FieldError>) -> Result<(), Vec<FieldError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// Prefix each field with its position in a batch, e.g. "operations[2].title"
+pub fn in_batch(index: usize, errors: Vec<FieldError>) -> Vec<FieldError> {
+    errors.into_iter()
+        .map(|error| FieldError { field: format!("operations[{}].{}", index, error.field), ..error })
+        .collect()
+}